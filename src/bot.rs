@@ -1,10 +1,17 @@
-use std::str;
+use std::mem;
+use permissions::Role;
+
+const REPLY_TAG: &'static str = "+draft/reply";
 
 #[derive(Debug)]
 #[derive(Clone)]
 pub enum BotJob {
     Join(String),
     PrivMsg((String, String)),
+    TaggedPrivMsg((::irc::IRCTags, String, String)),
+    Action((String, String)),
+    Notice((String, String)),
+    More(String),
 }
 
 #[derive(Debug)]
@@ -13,6 +20,7 @@ pub struct Bot<'a> {
     server: ::config::Server,
     job_queue: Vec<BotJob>,
     bot_state: BotState,
+    registry: ::plugin::Registry,
 }
 
 
@@ -20,145 +28,416 @@ pub struct Bot<'a> {
 #[derive(Debug)]
 struct BotState {
     connected: bool,
-    admin: Option<::irc::Client>,
+    sessions: ::session::Sessions,
+    channels: Vec<String>,
+    last_ctcp: i64,
+    last_tick: i64,
+    password_revoked: bool,
 }
 
 impl<'a> Bot<'a> {
-    pub fn handle_message(&mut self, message: ::irc::IRCMessage,
-                          conn_state: &::irc::ConnectionState) {
-        match message {
-            ::irc::IRCMessage::IRCServerMessage(m) => self.process_server_message(m, conn_state),
-            _ => {},
+    pub fn capabilities(&self) -> Vec<String> {
+        let mut capabilities: Vec<String> = ::capabilities::DEFAULT_CAPABILITIES.iter()
+            .map(|c| c.to_string())
+            .collect();
+        for capability in self.registry.capabilities() {
+            if !capabilities.iter().any(|c| c == capability) {
+                capabilities.push(capability.to_string());
+            }
+        }
+        if self.server.sasl.is_some() {
+            capabilities.push(::sasl::SASL.to_string());
         }
+        capabilities
     }
 
-    pub fn get_job(&mut self) -> Option<BotJob> {
+    pub fn get_job(&mut self, conn_state: &::irc::ConnectionState) -> Option<BotJob> {
         if self.job_queue.len() < 1 {
             return None;
         }
         let job = self.job_queue.remove(0);
+        if let BotJob::Join(ref channel) = job {
+            let casemapping = conn_state.isupport.casemapping;
+            if !self.bot_state.channels.iter().any(|c| casemapping.equals(c, channel)) {
+                self.bot_state.channels.push(channel.clone());
+            }
+        }
         Some(job)
+    }
+
+    pub fn disconnected(&mut self, reason: &str, conn_state: &::irc::ConnectionState) {
+        if self.bot_state.connected {
+            let mut event = ::event::new(::event::EventType::Disconnected, ::time::get_time().sec);
+            event.reason = Some(reason);
+            self.dispatch_event(&event, conn_state);
+        }
+        self.bot_state.connected = false;
+        self.bot_state.sessions.clear();
+        self.job_queue.clear();
+    }
+
+    pub fn tick(&mut self, now: i64, conn_state: &::irc::ConnectionState) {
+        if now == self.bot_state.last_tick {
+            return;
+        }
+        self.bot_state.last_tick = now;
+        if self.config.admin_password_revoked() && !self.bot_state.password_revoked {
+            self.bot_state.password_revoked = true;
+            self.bot_state.sessions.clear();
+        }
+        self.bot_state.sessions.expire(now);
+        self.dispatch_event(&::event::new(::event::EventType::Tick, now), conn_state);
+    }
 
+    pub fn dispatch_event(&mut self, event: &::event::Event,
+                          conn_state: &::irc::ConnectionState) -> bool {
+        let outcome = {
+            let mut networks = vec![self.server.host.as_str()];
+            if let Some(ref network) = conn_state.isupport.network {
+                networks.push(network.as_str());
+            }
+            self.registry.dispatch_event(event, &networks, conn_state)
+        };
+        self.job_queue.extend(outcome.jobs);
+        outcome.stop
     }
 
-    pub fn process_server_message(&mut self, message: ::irc::IRCServerMessage,
-                                 conn_state: &::irc::ConnectionState) {
-        let message_data = message.message.clone();
-        match message_data {
-            ::irc::IRCMessageType::PRIVMSG(m) => {
-                self.handle_privmsg(&m[..], message, conn_state);
+    pub fn process_server_message(&mut self, message: &::irc::IRCServerMessage,
+                                  conn_state: &::irc::ConnectionState) {
+        self.track_sessions(message, conn_state);
+        if self.role(message, conn_state) == Role::Ignored {
+            return;
+        }
+        let event = ::event::from_message(message, conn_state);
+        let stopped = self.dispatch_event(&event, conn_state) || replayed(message, conn_state);
+        match message.message {
+            ::irc::IRCMessageType::NUMERIC(::numeric::Numeric::EndOfMotd(_)) |
+            ::irc::IRCMessageType::NUMERIC(::numeric::Numeric::NoMotd(_)) => {
+                self.connected(message.time, conn_state);
             },
-            ::irc::IRCMessageType::INFO(i) => {
-                if i > 10 && !self.bot_state.connected {
-                    self.bot_state.connected = true;
-                    for channel in self.server.channels.clone() {
-                        self.job_queue.push(BotJob::Join(channel))
-                    }
-                }
+            _ => {},
+        }
+        if stopped {
+            return;
+        }
+        match message.message {
+            ::irc::IRCMessageType::PRIVMSG(_) => self.handle_privmsg(message, conn_state),
+            ::irc::IRCMessageType::CTCP((ref command, ref args)) => {
+                self.handle_ctcp(command, args, message);
             },
             _ => {},
         }
     }
 
-    pub fn msg(&mut self, target: String, from: ::irc::Entity, message: &str,
+    fn connected(&mut self, time: i64, conn_state: &::irc::ConnectionState) {
+        if self.bot_state.connected {
+            return;
+        }
+        self.bot_state.connected = true;
+        for channel in self.bot_state.channels.clone() {
+            self.job_queue.push(BotJob::Join(channel))
+        }
+        self.dispatch_event(&::event::new(::event::EventType::Connected, time), conn_state);
+    }
+
+    pub fn msg(&mut self, target: String, from: Option<::irc::Entity>, message: &str,
                conn_state: &::irc::ConnectionState) {
-        let client = match from {
-            ::irc::Entity::Client(c) => c,
-            _ => return,
+        let nick = match reply_target(target, from, conn_state) {
+            Some(n) => n,
+            None => return,
         };
-        let mut nick = target;
-        if &conn_state.nick == &nick {
-            nick = client.nick.clone();
-        }
         let message = message.to_string();
         self.job_queue.push(BotJob::PrivMsg((nick, message)));
     }
 
-    pub fn handle_privmsg(&mut self, privmsg: &[u8], message: ::irc::IRCServerMessage,
+    /// Answers `message` where it came from, threading the reply to it with
+    /// `+draft/reply` when the server supports message tags and gave a msgid.
+    pub fn reply(&mut self, message: &::irc::IRCServerMessage, text: &str,
+                 conn_state: &::irc::ConnectionState) {
+        let target = message.target.to_string();
+        let msgid = match message.line.tag("msgid") {
+            Some(id) if conn_state.caps.is_enabled(::capabilities::MESSAGE_TAGS) => id,
+            _ => return self.msg(target, message.from.clone(), text, conn_state),
+        };
+        let nick = match reply_target(target, message.from.clone(), conn_state) {
+            Some(n) => n,
+            None => return,
+        };
+        let mut tags = ::irc::IRCTags::new();
+        tags.insert(REPLY_TAG.to_string(), msgid.into_owned());
+        self.job_queue.push(BotJob::TaggedPrivMsg((tags, nick, text.to_string())));
+    }
+
+    pub fn styled_msg(&mut self, message: &::irc::IRCServerMessage,
+                      text: &::formatting::Formatter, conn_state: &::irc::ConnectionState) {
+        let text = styled_text(message.target, text, conn_state);
+        self.reply(message, &text, conn_state);
+    }
+
+    pub fn action(&mut self, target: String, from: Option<::irc::Entity>, action: &str,
+                  conn_state: &::irc::ConnectionState) {
+        let nick = match reply_target(target, from, conn_state) {
+            Some(n) => n,
+            None => return,
+        };
+        self.job_queue.push(BotJob::Action((nick, action.to_string())));
+    }
+
+    pub fn handle_ctcp(&mut self, command: &str, args: &str, message: &::irc::IRCServerMessage) {
+        let nick = match message.from {
+            Some(::irc::Entity::Client(ref c)) => c.nick.to_string(),
+            _ => return,
+        };
+        let reply = match command {
+            ::ctcp::VERSION => self.server.ctcp_version.clone(),
+            ::ctcp::PING => args.to_string(),
+            ::ctcp::TIME => {
+                match ::time::strftime("%a %b %d %H:%M:%S %Y %z", &::time::now()) {
+                    Ok(t) => t,
+                    Err(_) => return,
+                }
+            },
+            ::ctcp::CLIENTINFO => self.ctcp_commands().join(" "),
+            ::ctcp::SOURCE => {
+                match self.server.ctcp_source {
+                    Some(ref s) => s.clone(),
+                    None => return,
+                }
+            },
+            _ => return,
+        };
+        if message.time - self.bot_state.last_ctcp < self.server.ctcp_interval {
+            println!("Ignoring CTCP {command} from {nick}", command=command, nick=nick);
+            return;
+        }
+        self.bot_state.last_ctcp = message.time;
+        let reply = ::ctcp::encode(command, &reply);
+        self.job_queue.push(BotJob::Notice((nick, reply)));
+    }
+
+    fn ctcp_commands(&self) -> Vec<&'static str> {
+        let mut commands = vec![::ctcp::ACTION, ::ctcp::CLIENTINFO, ::ctcp::PING];
+        if self.server.ctcp_source.is_some() {
+            commands.push(::ctcp::SOURCE);
+        }
+        commands.push(::ctcp::TIME);
+        commands.push(::ctcp::VERSION);
+        commands
+    }
+
+    pub fn handle_privmsg(&mut self, message: &::irc::IRCServerMessage,
                           conn_state: &::irc::ConnectionState) {
-        if privmsg[0] != self.config.command_byte {
+        let stripped = match message.message {
+            ::irc::IRCMessageType::PRIVMSG(m) => ::formatting::strip(m),
+            _ => return,
+        };
+        let mut chars = stripped.chars();
+        if chars.next() != Some(self.command_prefix()) {
             return;
         }
-        let command_bytes = &privmsg[1..privmsg.len()];
-        let mut command_iter = command_bytes.splitn(2, |x| *x == b' ');
+        let mut command_iter = chars.as_str().splitn(2, ' ');
         let command = match command_iter.next() {
-            Some(c) => c,
-            None => return,
+            Some(c) if c.len() > 0 => c,
+            _ => return,
         };
-        let command = match str::from_utf8(command) {
-            Ok(c) => c,
+        let rest = command_iter.next().unwrap_or("");
+        let registry = mem::replace(&mut self.registry, ::plugin::Registry::default());
+        registry.dispatch(self, command, rest, message, conn_state);
+        self.registry = registry;
+    }
+
+    pub fn command_prefix(&self) -> char {
+        self.server.command_byte as char
+    }
+
+    pub fn config(&self) -> &::config::Config {
+        self.config
+    }
+
+    pub fn push_job(&mut self, job: BotJob) {
+        self.job_queue.push(job);
+    }
+
+    pub fn start_session(&mut self, message: &::irc::IRCServerMessage,
+                         conn_state: &::irc::ConnectionState) {
+        let from = match message.from {
+            Some(::irc::Entity::Client(ref c)) => c,
             _ => return,
         };
-        let rest = command_iter.next();
-        match command {
-            "auth" => self.auth(message, rest, conn_state),
-            "botsnack" => self.botsnack(message, conn_state),
-            _ => println!("Unhandled command: {}", command),
-        }
+        let account = account_of(message, conn_state);
+        self.bot_state.sessions.start(from, account, Role::Admin, message.time,
+                                      conn_state.isupport.casemapping);
     }
 
-    pub fn auth(&mut self, message: ::irc::IRCServerMessage, args: Option<&[u8]>,
-                conn_state: &::irc::ConnectionState) {
-        match args {
-            Some(p) => {
-                match str::from_utf8(p) {
-                    Ok(p) => {
-                        if p == self.config.admin_password {
-                            self.set_auth(&message.from);
-                            self.msg(message.target, message.from, "Authed!", conn_state);
-                            return;
-                        }
-                    },
-                    _ => {},
-                };
+    pub fn end_session(&mut self, nick: &str, conn_state: &::irc::ConnectionState) -> bool {
+        self.bot_state.sessions.end(nick, conn_state.isupport.casemapping)
+    }
+
+    pub fn session(&self, message: &::irc::IRCServerMessage,
+                   conn_state: &::irc::ConnectionState) -> Option<&::session::Session> {
+        match message.from {
+            Some(::irc::Entity::Client(ref c)) => {
+                self.bot_state.sessions.get(c, message.time, conn_state.isupport.casemapping)
             },
-            _ => {},
+            _ => None,
         }
-        self.msg(message.target, message.from, "Not authed. :(", conn_state);
-        return;
     }
 
-    pub fn botsnack(&mut self, message: ::irc::IRCServerMessage,
-                    conn_state: &::irc::ConnectionState) {
-        if !self.authed(&message.from) {
-            return;
+    pub fn clear_sessions(&mut self) {
+        self.bot_state.sessions.clear();
+    }
+
+    pub fn role(&self, message: &::irc::IRCServerMessage,
+                conn_state: &::irc::ConnectionState) -> Role {
+        let from = match message.from {
+            Some(::irc::Entity::Client(ref c)) => c,
+            _ => return Role::User,
+        };
+        let account = account_of(message, conn_state);
+        let account = account.as_ref().map(|a| a.as_str());
+        let role = ::permissions::role_of(&self.server.users, from, account,
+                                          conn_state.isupport.casemapping);
+        match self.session(message, conn_state) {
+            Some(s) if s.role > role => s.role,
+            _ => role,
         }
-        self.msg(message.target, message.from, ":)", conn_state);
     }
 
-    pub fn set_auth(&mut self, entity: &::irc::Entity) {
-        match entity {
-            &::irc::Entity::Client(ref c) => {
-                self.bot_state.admin = Some(c.clone());
+    pub fn authed(&self, message: &::irc::IRCServerMessage, role: Role,
+                  conn_state: &::irc::ConnectionState) -> bool {
+        self.role(message, conn_state) >= role
+    }
+
+    fn track_sessions(&mut self, message: &::irc::IRCServerMessage,
+                      conn_state: &::irc::ConnectionState) {
+        let casemapping = conn_state.isupport.casemapping;
+        let sessions = &mut self.bot_state.sessions;
+        match message.message {
+            ::irc::IRCMessageType::KILL((ref nick, _)) => {
+                sessions.end(nick, casemapping);
+                return;
+            },
+            ::irc::IRCMessageType::KICK((_, ref nick, _)) => {
+                left_channel(sessions, nick, conn_state);
+                return;
             },
             _ => {},
         }
+        let from = match message.from {
+            Some(::irc::Entity::Client(ref c)) => c,
+            _ => return,
+        };
+        match message.message {
+            ::irc::IRCMessageType::PRIVMSG(_) | ::irc::IRCMessageType::ACTION(_) |
+            ::irc::IRCMessageType::CTCP(_) => sessions.touch(from, message.time, casemapping),
+            ::irc::IRCMessageType::NICK(ref nick) => sessions.rename(&from.nick, nick, casemapping),
+            ::irc::IRCMessageType::ACCOUNT(account) => {
+                sessions.account_changed(&from.nick, account, casemapping);
+            },
+            ::irc::IRCMessageType::QUIT(_) | ::irc::IRCMessageType::CHGHOST(_) => {
+                sessions.end(&from.nick, casemapping);
+            },
+            ::irc::IRCMessageType::PART(_) => left_channel(sessions, &from.nick, conn_state),
+            _ => {},
+        }
     }
+}
 
-    pub fn authed(&mut self, from: &::irc::Entity) -> bool {
-        let from = match from {
-            &::irc::Entity::Client(ref c) => c,
-            _ => return false,
-        };
-        let admin = &self.bot_state.admin;
-        let admin = match admin {
-            &Some(ref a) => a,
-            _ => return false,
-        };
-        return *admin == *from;
+pub fn account_of(message: &::irc::IRCServerMessage,
+                  conn_state: &::irc::ConnectionState) -> Option<String> {
+    if let Some(account) = message.line.tag("account") {
+        return Some(account.into_owned());
+    }
+    match message.from {
+        Some(::irc::Entity::Client(ref c)) => conn_state.channels.account(&c.nick),
+        _ => None,
+    }
+}
+
+fn replayed(message: &::irc::IRCServerMessage, conn_state: &::irc::ConnectionState) -> bool {
+    let batch = match message.line.tag("batch") {
+        Some(b) => b,
+        None => return false,
+    };
+    match conn_state.batches.get(batch.as_ref()) {
+        Some(kind) => ::capabilities::PLAYBACK_BATCHES.contains(&kind.as_str()),
+        None => false,
+    }
+}
+
+fn left_channel(sessions: &mut ::session::Sessions, nick: &str,
+                conn_state: &::irc::ConnectionState) {
+    if conn_state.isupport.casemapping.equals(nick, &conn_state.nick) {
+        sessions.retain(|s| conn_state.channels.shared_channels(&s.nick).len() > 0);
+        return;
+    }
+    if conn_state.channels.shared_channels(nick).len() < 1 {
+        sessions.end(nick, conn_state.isupport.casemapping);
+    }
+}
+
+pub fn reply_target(target: String, from: Option<::irc::Entity>,
+                conn_state: &::irc::ConnectionState) -> Option<String> {
+    let client = match from {
+        Some(::irc::Entity::Client(c)) => c,
+        _ => return None,
+    };
+    if conn_state.isupport.is_channel(&target) {
+        return Some(target);
+    }
+    Some(client.nick.into_owned())
+}
+
+/// Channels with +c reject or mangle formatting codes, so they get the plain text.
+fn styled_text(target: &str, text: &::formatting::Formatter,
+               conn_state: &::irc::ConnectionState) -> String {
+    match conn_state.channels.allows_colors(target) {
+        true => text.build(),
+        false => text.plain(),
     }
 }
 
 pub fn new<'a>(config: &'a ::config::Config, server: ::config::Server) -> Bot {
+    let channels = server.channels.clone();
+    let idle_timeout = server.auth_idle_timeout;
     Bot {
         config: config,
         server: server,
         job_queue: Vec::new(),
+        registry: ::plugins::registry(),
         bot_state: BotState {
             connected: false,
-            admin: None,
+            sessions: ::session::new(idle_timeout),
+            channels: channels,
+            last_ctcp: 0,
+            last_tick: 0,
+            password_revoked: false,
         },
     }
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use formatting::{self, Color};
+    use irc::{Client, ConnectionState};
+    use super::styled_text;
+
+    #[test]
+    fn no_colours_in_plus_c_channels() {
+        let mut conn_state = ConnectionState::default();
+        let client = Client {
+            nick: "korasho".into(),
+            username: "korasho".into(),
+            address: "example.org".into(),
+        };
+        conn_state.channels.join("#korasho", &client, None, true);
+        let text = formatting::new().bold("!help").text(" - ").color("red", Color::Index(4), None);
+        assert_eq!(styled_text("#korasho", &text, &conn_state), "\x02!help\x0f - \x0304red\x0f");
+        assert_eq!(styled_text("alice", &text, &conn_state), "\x02!help\x0f - \x0304red\x0f");
+        conn_state.channels.apply_modes(&conn_state.isupport, "#korasho", &["+c"], false);
+        assert_eq!(styled_text("#KORASHO", &text, &conn_state), "!help - red");
+        assert_eq!(styled_text("alice", &text, &conn_state), "\x02!help\x0f - \x0304red\x0f");
+    }
+}
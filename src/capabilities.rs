@@ -0,0 +1,140 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+pub const MULTI_PREFIX: &'static str = "multi-prefix";
+pub const SERVER_TIME: &'static str = "server-time";
+pub const AWAY_NOTIFY: &'static str = "away-notify";
+pub const ACCOUNT_NOTIFY: &'static str = "account-notify";
+pub const EXTENDED_JOIN: &'static str = "extended-join";
+pub const MESSAGE_TAGS: &'static str = "message-tags";
+pub const CAP_NOTIFY: &'static str = "cap-notify";
+pub const ECHO_MESSAGE: &'static str = "echo-message";
+pub const BATCH: &'static str = "batch";
+pub const ACCOUNT_TAG: &'static str = "account-tag";
+
+pub const PLAYBACK_BATCHES: [&'static str; 2] = ["chathistory", "znc.in/playback"];
+
+pub const DEFAULT_CAPABILITIES: [&'static str; 10] = [
+    MULTI_PREFIX,
+    SERVER_TIME,
+    AWAY_NOTIFY,
+    ACCOUNT_NOTIFY,
+    EXTENDED_JOIN,
+    MESSAGE_TAGS,
+    CAP_NOTIFY,
+    ECHO_MESSAGE,
+    BATCH,
+    ACCOUNT_TAG,
+];
+
+#[derive(Default)]
+#[derive(Debug)]
+pub struct Capabilities {
+    pub available: BTreeMap<String, String>,
+    pub enabled: BTreeSet<String>,
+    pub requested: BTreeSet<String>,
+    pub negotiating: bool,
+}
+
+impl Capabilities {
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.enabled.contains(name)
+    }
+
+    pub fn value(&self, name: &str) -> Option<&String> {
+        self.available.get(name)
+    }
+
+    pub fn add_available(&mut self, list: &str) -> Vec<String> {
+        let mut added = Vec::new();
+        for (name, value) in parse_cap_list(list) {
+            added.push(name.clone());
+            self.available.insert(name, value);
+        }
+        added
+    }
+
+    pub fn remove_available(&mut self, list: &str) {
+        for (name, _) in parse_cap_list(list) {
+            self.available.remove(&name);
+            self.enabled.remove(&name);
+            self.requested.remove(&name);
+        }
+    }
+
+    pub fn acknowledge(&mut self, list: &str) {
+        for (name, _) in parse_cap_list(list) {
+            self.requested.remove(name.trim_start_matches('-'));
+            if name.starts_with('-') {
+                self.enabled.remove(name.trim_start_matches('-'));
+                continue;
+            }
+            self.enabled.insert(name);
+        }
+    }
+
+    pub fn reject(&mut self, list: &str) {
+        for (name, _) in parse_cap_list(list) {
+            self.requested.remove(name.trim_start_matches('-'));
+        }
+    }
+
+    pub fn to_request(&self, wanted: &Vec<String>) -> Vec<String> {
+        wanted.iter()
+            .filter(|w| self.available.contains_key(*w))
+            .filter(|w| !self.enabled.contains(*w) && !self.requested.contains(*w))
+            .cloned()
+            .collect()
+    }
+}
+
+pub fn parse_cap_list(list: &str) -> Vec<(String, String)> {
+    list.split(' ').filter(|c| c.len() > 0).map(|c| {
+        let mut cap_iter = c.splitn(2, '=');
+        let name = cap_iter.next().unwrap_or("").to_string();
+        let value = cap_iter.next().unwrap_or("").to_string();
+        (name, value)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_cap_list, Capabilities};
+
+    fn wanted(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn cap_lists_with_values() {
+        assert_eq!(parse_cap_list("sasl=PLAIN,EXTERNAL  batch "),
+                   vec![("sasl".to_string(), "PLAIN,EXTERNAL".to_string()),
+                        ("batch".to_string(), "".to_string())]);
+    }
+
+    #[test]
+    fn only_available_caps_are_requested_once() {
+        let mut caps = Capabilities::default();
+        caps.add_available("echo-message batch server-time");
+        let wanted = wanted(&["batch", "echo-message", "account-tag"]);
+        assert_eq!(caps.to_request(&wanted), vec!["batch", "echo-message"]);
+        caps.requested.insert("batch".to_string());
+        caps.acknowledge("echo-message");
+        assert!(caps.is_enabled("echo-message"));
+        assert_eq!(caps.to_request(&wanted), Vec::<String>::new());
+        caps.reject("batch");
+        caps.add_available("account-tag");
+        assert_eq!(caps.to_request(&wanted), vec!["batch", "account-tag"]);
+    }
+
+    #[test]
+    fn removed_caps_are_disabled() {
+        let mut caps = Capabilities::default();
+        caps.add_available("echo-message batch");
+        caps.acknowledge("echo-message batch");
+        caps.acknowledge("-batch");
+        assert!(!caps.is_enabled("batch"));
+        caps.remove_available("echo-message");
+        assert!(!caps.is_enabled("echo-message"));
+        assert!(caps.value("echo-message").is_none());
+    }
+}
@@ -0,0 +1,128 @@
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum CaseMapping {
+    Ascii,
+    Rfc1459,
+    StrictRfc1459,
+    Rfc7613,
+}
+
+impl Default for CaseMapping {
+    fn default() -> CaseMapping {
+        CaseMapping::Rfc1459
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Eq)]
+#[derive(Hash)]
+#[derive(PartialOrd)]
+#[derive(Ord)]
+pub struct IRCKey(String);
+
+impl IRCKey {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl CaseMapping {
+    pub fn from_token(value: &str) -> CaseMapping {
+        match value {
+            "ascii" => CaseMapping::Ascii,
+            "strict-rfc1459" => CaseMapping::StrictRfc1459,
+            "rfc7613" => CaseMapping::Rfc7613,
+            _ => CaseMapping::Rfc1459,
+        }
+    }
+
+    pub fn to_lower(&self, name: &str) -> String {
+        match *self {
+            CaseMapping::Rfc7613 => name.chars().map(fold_width).collect::<String>().to_lowercase(),
+            _ => name.chars().map(|c| self.lower_char(c)).collect(),
+        }
+    }
+
+    pub fn key(&self, name: &str) -> IRCKey {
+        IRCKey(self.to_lower(name))
+    }
+
+    pub fn equals(&self, a: &str, b: &str) -> bool {
+        match *self {
+            CaseMapping::Rfc7613 => self.to_lower(a) == self.to_lower(b),
+            _ => {
+                a.len() == b.len() &&
+                    a.chars().zip(b.chars()).all(|(x, y)| self.lower_char(x) == self.lower_char(y))
+            },
+        }
+    }
+
+    fn lower_char(&self, c: char) -> char {
+        match (*self, c) {
+            (CaseMapping::Rfc1459, '^') => '~',
+            (CaseMapping::Rfc1459, '[') | (CaseMapping::StrictRfc1459, '[') => '{',
+            (CaseMapping::Rfc1459, ']') | (CaseMapping::StrictRfc1459, ']') => '}',
+            (CaseMapping::Rfc1459, '\\') | (CaseMapping::StrictRfc1459, '\\') => '|',
+            _ => c.to_ascii_lowercase(),
+        }
+    }
+}
+
+fn fold_width(c: char) -> char {
+    match c {
+        '\u{ff01}'..='\u{ff5e}' => ::std::char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+        '\u{3000}' => ' ',
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CaseMapping;
+
+    #[test]
+    fn tokens() {
+        assert_eq!(CaseMapping::from_token("ascii"), CaseMapping::Ascii);
+        assert_eq!(CaseMapping::from_token("rfc1459"), CaseMapping::Rfc1459);
+        assert_eq!(CaseMapping::from_token("strict-rfc1459"), CaseMapping::StrictRfc1459);
+        assert_eq!(CaseMapping::from_token("rfc7613"), CaseMapping::Rfc7613);
+        assert_eq!(CaseMapping::from_token("unknown"), CaseMapping::Rfc1459);
+    }
+
+    #[test]
+    fn rfc1459_folds_brackets_and_caret() {
+        let rfc1459 = CaseMapping::Rfc1459;
+        assert_eq!(rfc1459.to_lower("Nick[A]\\^"), "nick{a}|~");
+        assert!(rfc1459.equals("[Korasho]", "{korasho}"));
+        assert!(rfc1459.equals("a\\b^", "A|B~"));
+        assert_eq!(rfc1459.key("#Chan[1]"), rfc1459.key("#chan{1}"));
+    }
+
+    #[test]
+    fn strict_rfc1459_leaves_caret_alone() {
+        let strict = CaseMapping::StrictRfc1459;
+        assert!(strict.equals("[a]\\", "{A}|"));
+        assert!(!strict.equals("a^", "a~"));
+    }
+
+    #[test]
+    fn ascii_only_folds_letters() {
+        let ascii = CaseMapping::Ascii;
+        assert!(ascii.equals("Korasho", "kORASHO"));
+        assert!(!ascii.equals("[a]", "{a}"));
+        assert!(!ascii.equals("\u{c9}", "\u{e9}"));
+        assert!(!ascii.equals("nick", "nick_"));
+    }
+
+    #[test]
+    fn rfc7613_folds_unicode_and_width() {
+        let rfc7613 = CaseMapping::Rfc7613;
+        assert!(rfc7613.equals("\u{c9}T\u{c9}", "\u{e9}t\u{e9}"));
+        assert!(rfc7613.equals("\u{ff2e}ick", "nick"));
+        assert!(!rfc7613.equals("[a]", "{a}"));
+    }
+}
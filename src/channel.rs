@@ -0,0 +1,465 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use casemap::{CaseMapping, IRCKey};
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Member {
+    pub nick: String,
+    pub username: String,
+    pub address: String,
+    pub account: Option<String>,
+    pub modes: String,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Topic {
+    pub text: String,
+    pub setter: String,
+    pub time: i64,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Channel {
+    pub name: String,
+    pub members: HashMap<IRCKey, Member>,
+    pub modes: BTreeMap<char, Option<String>>,
+    pub lists: BTreeMap<char, Vec<String>>,
+    pub topic: Option<Topic>,
+}
+
+impl Channel {
+    pub fn member(&self, nick: &IRCKey) -> Option<&Member> {
+        self.members.get(nick)
+    }
+
+    pub fn has_mode(&self, mode: char) -> bool {
+        self.modes.contains_key(&mode)
+    }
+}
+
+#[derive(Default)]
+#[derive(Debug)]
+pub struct Channels {
+    casemapping: CaseMapping,
+    channels: HashMap<IRCKey, Channel>,
+    names_in_progress: HashSet<IRCKey>,
+}
+
+impl Channels {
+    pub fn set_casemapping(&mut self, casemapping: CaseMapping) {
+        if self.casemapping == casemapping {
+            return;
+        }
+        self.casemapping = casemapping;
+        let channels: Vec<Channel> = self.channels.drain().map(|(_, c)| c).collect();
+        for mut channel in channels {
+            let members: Vec<Member> = channel.members.drain().map(|(_, m)| m).collect();
+            for member in members {
+                channel.members.insert(casemapping.key(&member.nick), member);
+            }
+            self.channels.insert(casemapping.key(&channel.name), channel);
+        }
+        self.names_in_progress.clear();
+    }
+
+    pub fn get(&self, channel: &str) -> Option<&Channel> {
+        self.channels.get(&self.casemapping.key(channel))
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.channels.values().map(|c| c.name.clone()).collect()
+    }
+
+    pub fn has_member_mode(&self, channel: &str, nick: &str, mode: char) -> bool {
+        let nick = self.casemapping.key(nick);
+        match self.get(channel).and_then(|c| c.member(&nick)) {
+            Some(m) => m.modes.contains(mode),
+            None => false,
+        }
+    }
+
+    pub fn is_opped(&self, channel: &str, nick: &str) -> bool {
+        self.has_member_mode(channel, nick, 'o')
+    }
+
+    pub fn is_voiced(&self, channel: &str, nick: &str) -> bool {
+        self.has_member_mode(channel, nick, 'v')
+    }
+
+    pub fn allows_colors(&self, channel: &str) -> bool {
+        match self.get(channel) {
+            Some(c) => !c.has_mode('c'),
+            None => true,
+        }
+    }
+
+    pub fn account(&self, nick: &str) -> Option<String> {
+        let nick = self.casemapping.key(nick);
+        self.channels.values()
+            .filter_map(|c| c.member(&nick).and_then(|m| m.account.clone()))
+            .next()
+    }
+
+    pub fn shared_channels(&self, nick: &str) -> Vec<String> {
+        let nick = self.casemapping.key(nick);
+        self.channels.values()
+            .filter(|c| c.member(&nick).is_some())
+            .map(|c| c.name.clone())
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.channels.clear();
+        self.names_in_progress.clear();
+    }
+
+    pub fn join(&mut self, channel: &str, client: &::irc::Client, account: Option<String>,
+                ours: bool) {
+        if ours {
+            self.channels.insert(self.casemapping.key(channel), Channel {
+                name: channel.to_string(),
+                members: HashMap::new(),
+                modes: BTreeMap::new(),
+                lists: BTreeMap::new(),
+                topic: None,
+            });
+        }
+        let channel = match self.channels.get_mut(&self.casemapping.key(channel)) {
+            Some(c) => c,
+            None => return,
+        };
+        channel.members.insert(self.casemapping.key(&client.nick), Member {
+            nick: client.nick.to_string(),
+            username: client.username.to_string(),
+            address: client.address.to_string(),
+            account: account,
+            modes: "".to_string(),
+        });
+    }
+
+    pub fn part(&mut self, channel: &str, nick: &str, ours: bool) {
+        if ours {
+            self.channels.remove(&self.casemapping.key(channel));
+            return;
+        }
+        if let Some(c) = self.channels.get_mut(&self.casemapping.key(channel)) {
+            c.members.remove(&self.casemapping.key(nick));
+        }
+    }
+
+    pub fn quit(&mut self, nick: &str) {
+        let nick = self.casemapping.key(nick);
+        for channel in self.channels.values_mut() {
+            channel.members.remove(&nick);
+        }
+    }
+
+    pub fn rename(&mut self, old_nick: &str, new_nick: &str) {
+        let old_key = self.casemapping.key(old_nick);
+        let new_key = self.casemapping.key(new_nick);
+        for channel in self.channels.values_mut() {
+            if let Some(mut member) = channel.members.remove(&old_key) {
+                member.nick = new_nick.to_string();
+                channel.members.insert(new_key.clone(), member);
+            }
+        }
+    }
+
+    pub fn set_account(&mut self, nick: &str, account: Option<String>) {
+        let nick = self.casemapping.key(nick);
+        for channel in self.channels.values_mut() {
+            if let Some(member) = channel.members.get_mut(&nick) {
+                member.account = account.clone();
+            }
+        }
+    }
+
+    pub fn set_host(&mut self, nick: &str, username: &str, address: &str) {
+        let nick = self.casemapping.key(nick);
+        for channel in self.channels.values_mut() {
+            if let Some(member) = channel.members.get_mut(&nick) {
+                member.username = username.to_string();
+                member.address = address.to_string();
+            }
+        }
+    }
+
+    pub fn set_topic(&mut self, channel: &str, text: Option<&str>, setter: Option<&str>,
+                     time: Option<i64>) {
+        let channel = match self.channels.get_mut(&self.casemapping.key(channel)) {
+            Some(c) => c,
+            None => return,
+        };
+        let mut topic = channel.topic.take().unwrap_or(Topic {
+            text: "".to_string(),
+            setter: "".to_string(),
+            time: 0,
+        });
+        if let Some(t) = text {
+            topic.text = t.to_string();
+        }
+        if let Some(s) = setter {
+            topic.setter = s.to_string();
+        }
+        if let Some(t) = time {
+            topic.time = t;
+        }
+        if topic.text.len() > 0 {
+            channel.topic = Some(topic);
+        }
+    }
+
+    pub fn add_names(&mut self, isupport: &::isupport::ISupport, channel: &str, names: &[String]) {
+        let channel_key = self.casemapping.key(channel);
+        let channel = match self.channels.get_mut(&channel_key) {
+            Some(c) => c,
+            None => return,
+        };
+        if self.names_in_progress.insert(channel_key) {
+            channel.members.clear();
+        }
+        for name in names {
+            let mut modes = String::new();
+            let mut mask = name.as_str();
+            while let Some(symbol) = mask.chars().next() {
+                match isupport.prefix_mode(symbol) {
+                    Some(m) => modes.push(m),
+                    None => break,
+                }
+                mask = &mask[symbol.len_utf8()..];
+            }
+            let client = match ::irc_parser::parse_from(mask) {
+                Some(::irc::Entity::Client(c)) => c,
+                _ => ::irc::Client {
+                    nick: Cow::Borrowed(mask),
+                    username: Cow::Borrowed(""),
+                    address: Cow::Borrowed(""),
+                },
+            };
+            channel.members.insert(self.casemapping.key(&client.nick), Member {
+                nick: client.nick.into_owned(),
+                username: client.username.into_owned(),
+                address: client.address.into_owned(),
+                account: None,
+                modes: modes,
+            });
+        }
+    }
+
+    pub fn end_names(&mut self, channel: &str) {
+        self.names_in_progress.remove(&self.casemapping.key(channel));
+    }
+
+    pub fn apply_modes<S: AsRef<str>>(&mut self, isupport: &::isupport::ISupport, channel: &str,
+                                      modes: &[S], reset: bool) {
+        let channel = match self.channels.get_mut(&self.casemapping.key(channel)) {
+            Some(c) => c,
+            None => return,
+        };
+        if reset {
+            channel.modes.clear();
+        }
+        let mode_string = match modes.first() {
+            Some(m) => m,
+            None => return,
+        };
+        let mut args = modes[1..].iter();
+        let mut adding = true;
+        for mode in mode_string.as_ref().chars() {
+            match mode {
+                '+' => adding = true,
+                '-' => adding = false,
+                _ => {
+                    let arg = match isupport.mode_takes_param(mode, adding) {
+                        true => args.next().map(|a| a.as_ref().to_string()),
+                        false => None,
+                    };
+                    apply_mode(isupport, channel, mode, adding, arg);
+                },
+            }
+        }
+    }
+}
+
+fn apply_mode(isupport: &::isupport::ISupport, channel: &mut Channel, mode: char, adding: bool,
+              arg: Option<String>) {
+    if isupport.prefix_symbol(mode).is_some() {
+        let nick = match arg {
+            Some(a) => a,
+            None => return,
+        };
+        if let Some(member) = channel.members.get_mut(&isupport.casemapping.key(&nick)) {
+            let mut member_modes: Vec<char> = member.modes.chars().filter(|m| *m != mode).collect();
+            if adding {
+                member_modes.push(mode);
+                member_modes.sort_by_key(|m| isupport.prefix_rank(*m));
+            }
+            member.modes = member_modes.into_iter().collect();
+        }
+        return;
+    }
+    if isupport.list_modes.contains(mode) {
+        let mask = match arg {
+            Some(a) => a,
+            None => return,
+        };
+        let list = channel.lists.entry(mode).or_insert(Vec::new());
+        list.retain(|m| *m != mask);
+        if adding {
+            list.push(mask);
+        }
+        return;
+    }
+    if adding {
+        channel.modes.insert(mode, arg);
+    } else {
+        channel.modes.remove(&mode);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use casemap::CaseMapping;
+    use irc::Client;
+    use isupport::ISupport;
+    use super::Channels;
+
+    fn client(nick: &str) -> Client<'static> {
+        Client {
+            nick: nick.to_string().into(),
+            username: "user".into(),
+            address: "example.org".into(),
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn joined(nicks: &[&str]) -> Channels {
+        let mut channels = Channels::default();
+        channels.join("#korasho", &client("korasho"), None, true);
+        for nick in nicks {
+            channels.join("#korasho", &client(nick), None, false);
+        }
+        channels
+    }
+
+    fn members(channels: &Channels) -> Vec<String> {
+        let mut members: Vec<String> = channels.get("#korasho").unwrap()
+            .members.values()
+            .map(|m| m.nick.clone())
+            .collect();
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn join_tracks_members_of_our_channels_only() {
+        let mut channels = joined(&["alice"]);
+        channels.join("#elsewhere", &client("bob"), Some("bob".to_string()), false);
+        assert_eq!(channels.names(), vec!["#korasho"]);
+        assert_eq!(members(&channels), vec!["alice", "korasho"]);
+        channels.join("#KORASHO", &client("bob"), Some("bobby".to_string()), false);
+        assert_eq!(channels.account("BOB"), Some("bobby".to_string()));
+        assert_eq!(channels.shared_channels("bob"), vec!["#korasho"]);
+    }
+
+    #[test]
+    fn names_replace_members_across_replies() {
+        let isupport = ISupport::default();
+        let mut channels = joined(&["stale"]);
+        channels.add_names(&isupport, "#korasho", &names(&["@alice", "+bob!b@host"]));
+        channels.add_names(&isupport, "#korasho", &names(&["@+carol", "korasho"]));
+        channels.end_names("#korasho");
+        assert_eq!(members(&channels), vec!["alice", "bob", "carol", "korasho"]);
+        assert!(channels.is_opped("#korasho", "alice"));
+        assert!(channels.is_voiced("#korasho", "bob"));
+        assert!(channels.is_opped("#korasho", "carol") && channels.is_voiced("#korasho", "carol"));
+        let bob = channels.get("#korasho").unwrap().member(&CaseMapping::Rfc1459.key("bob"))
+            .unwrap().clone();
+        assert_eq!((bob.username.as_str(), bob.address.as_str()), ("b", "host"));
+
+        channels.add_names(&isupport, "#korasho", &names(&["korasho"]));
+        channels.end_names("#korasho");
+        assert_eq!(members(&channels), vec!["korasho"]);
+    }
+
+    #[test]
+    fn member_modes_follow_mode_changes() {
+        let isupport = ISupport::default();
+        let mut channels = joined(&["alice", "bob"]);
+        channels.apply_modes(&isupport, "#korasho", &["+v", "alice"], false);
+        assert!(channels.is_voiced("#korasho", "alice"));
+        channels.apply_modes(&isupport, "#korasho", &["+o-v", "alice", "alice"], false);
+        assert!(channels.is_opped("#korasho", "alice"));
+        assert!(!channels.is_voiced("#korasho", "alice"));
+        channels.apply_modes(&isupport, "#korasho", &["+vo", "ALICE", "bob"], false);
+        let alice = channels.get("#korasho").unwrap().member(&CaseMapping::Rfc1459.key("alice"))
+            .unwrap().clone();
+        assert_eq!(alice.modes, "ov");
+        assert!(channels.is_opped("#korasho", "bob"));
+    }
+
+    #[test]
+    fn list_modes_and_parameter_modes() {
+        let isupport = ISupport::default();
+        let mut channels = joined(&[]);
+        channels.apply_modes(&isupport, "#korasho", &["+bkl", "*!*@spam", "secret", "10"], false);
+        channels.apply_modes(&isupport, "#korasho", &["+bc", "*!*@flood"], false);
+        {
+            let channel = channels.get("#korasho").unwrap();
+            assert_eq!(channel.lists.get(&'b').unwrap(), &vec!["*!*@spam", "*!*@flood"]);
+            assert_eq!(channel.modes.get(&'k'), Some(&Some("secret".to_string())));
+            assert_eq!(channel.modes.get(&'l'), Some(&Some("10".to_string())));
+            assert!(!channel.has_mode('b'));
+        }
+        assert!(!channels.allows_colors("#korasho"));
+        channels.apply_modes(&isupport, "#korasho", &["-blkc", "*!*@spam", "secret"], false);
+        let channel = channels.get("#korasho").unwrap();
+        assert_eq!(channel.lists.get(&'b').unwrap(), &vec!["*!*@flood"]);
+        assert!(!channel.has_mode('k') && !channel.has_mode('l') && !channel.has_mode('c'));
+        assert!(channels.allows_colors("#korasho"));
+    }
+
+    #[test]
+    fn nick_changes_keep_modes() {
+        let isupport = ISupport::default();
+        let mut channels = joined(&["alice"]);
+        channels.apply_modes(&isupport, "#korasho", &["+o", "alice"], false);
+        channels.rename("Alice", "Alicia");
+        assert_eq!(members(&channels), vec!["Alicia", "korasho"]);
+        assert!(channels.is_opped("#korasho", "alicia"));
+        assert!(!channels.is_opped("#korasho", "alice"));
+    }
+
+    #[test]
+    fn parting_or_being_kicked() {
+        let mut channels = joined(&["alice", "bob"]);
+        channels.part("#korasho", "alice", false);
+        assert_eq!(members(&channels), vec!["bob", "korasho"]);
+        channels.part("#korasho", "korasho", true);
+        assert!(channels.get("#korasho").is_none());
+        assert!(channels.shared_channels("bob").is_empty());
+        channels.join("#korasho", &client("bob"), None, false);
+        assert!(channels.get("#korasho").is_none());
+    }
+
+    #[test]
+    fn casemapping_changes_rekey_channels_and_members() {
+        let mut channels = Channels::default();
+        channels.set_casemapping(CaseMapping::Ascii);
+        channels.join("#chan[1]", &client("korasho"), None, true);
+        channels.join("#chan[1]", &client("nick[a]"), Some("acct".to_string()), false);
+        assert!(channels.get("#chan{1}").is_none());
+        assert_eq!(channels.account("nick{a}"), None);
+
+        channels.set_casemapping(CaseMapping::Rfc1459);
+        assert_eq!(channels.get("#CHAN{1}").unwrap().name, "#chan[1]");
+        assert_eq!(channels.account("NICK{A}"), Some("acct".to_string()));
+        assert_eq!(channels.shared_channels("nick{a}"), vec!["#chan[1]"]);
+    }
+}
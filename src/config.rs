@@ -1,19 +1,103 @@
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
 use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use toml::Value;
+use encoding::{Encoding, DEFAULT_ENCODINGS};
+use permissions::{Role, User};
 
 const DEFAULT_BOT_NAME: &'static str = "korasho";
 const DEFAULT_USERNAME: &'static str = "korasho";
 const DEFAULT_REALNAME: &'static str = "korasho.bot";
+const DEFAULT_LIMITS: Limits = Limits {
+    ping_interval: 120,
+    ping_timeout: 180,
+    flood_burst: 5,
+    flood_rate: 2000,
+    max_lines: 4,
+};
+const DEFAULT_CTCP_INTERVAL: i64 = 2;
+const DEFAULT_AUTH_IDLE_TIMEOUT: i64 = 3600;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum SaslMechanism {
+    Plain,
+    External,
+}
+
+impl fmt::Display for SaslMechanism {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &SaslMechanism::Plain => write!(f, "PLAIN"),
+            &SaslMechanism::External => write!(f, "EXTERNAL"),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Sasl {
+    pub mechanism: SaslMechanism,
+    pub account: String,
+    pub password: String,
+    pub required: bool,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Address {
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
 
 #[derive(Debug)]
 #[derive(Clone)]
 pub struct Server {
     pub host: String,
     pub port: u16,
+    pub alternates: Vec<Address>,
     pub secure: bool,
     pub channels: Vec<String>,
+    pub nick: String,
+    pub alt: String,
+    pub username: String,
+    pub realname: String,
+    pub password: Option<String>,
+    pub command_byte: u8,
+    pub ping_interval: i64,
+    pub ping_timeout: i64,
+    pub flood_burst: u32,
+    pub flood_rate: u64,
+    pub max_lines: usize,
+    pub ctcp_version: String,
+    pub ctcp_source: Option<String>,
+    pub ctcp_interval: i64,
+    pub encodings: Vec<Encoding>,
+    pub users: Vec<User>,
+    pub auth_idle_timeout: i64,
+    pub sasl: Option<Sasl>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+}
+
+impl Server {
+    pub fn addresses(&self) -> Vec<Address> {
+        let mut addresses = vec![Address {
+            host: self.host.clone(),
+            port: self.port,
+        }];
+        addresses.extend(self.alternates.iter().cloned());
+        addresses
+    }
 }
 
 impl fmt::Display for Server {
@@ -30,8 +114,39 @@ pub struct Config {
     pub alt: String,
     pub username: String,
     pub realname: String,
-    pub admin_password: String,
+    pub admin_password: ::password::PasswordHash,
+    pub admin_password_revoked: Arc<AtomicBool>,
+    pub revoked_passwords_file: String,
     pub command_byte: u8,
+    pub ping_interval: i64,
+    pub ping_timeout: i64,
+    pub flood_burst: u32,
+    pub flood_rate: u64,
+    pub max_lines: usize,
+    pub ctcp_version: String,
+    pub ctcp_source: Option<String>,
+    pub ctcp_interval: i64,
+    pub encodings: Vec<Encoding>,
+    pub users: Vec<User>,
+    pub auth_idle_timeout: i64,
+}
+
+impl Config {
+    pub fn admin_password_revoked(&self) -> bool {
+        self.admin_password_revoked.load(Ordering::SeqCst)
+    }
+
+    pub fn revoke_admin_password(&self) -> Result<(), String> {
+        self.admin_password_revoked.store(true, Ordering::SeqCst);
+        let file = &self.revoked_passwords_file;
+        let result = OpenOptions::new().create(true).append(true).open(file).and_then(|mut f| {
+            writeln!(f, "{hash}", hash=self.admin_password)
+        });
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("{file}: {err}", file=file, err=err)),
+        }
+    }
 }
 
 pub fn read_config(filename: &String) -> Config {
@@ -44,8 +159,11 @@ pub fn read_config(filename: &String) -> Config {
         Err(e) => panic!("Unable to open config file: {}", e),
         _ => {},
     };
-    let new_input = input.clone();
-    let mut parser = ::toml::Parser::new(&new_input);
+    parse_config(filename, &input)
+}
+
+fn parse_config(filename: &str, input: &str) -> Config {
+    let mut parser = ::toml::Parser::new(input);
     let rawtoml = match parser.parse() {
         Some(rawtoml) => rawtoml,
         None => {
@@ -75,18 +193,31 @@ pub fn read_config(filename: &String) -> Config {
         Ok(n) => n,
         _ => DEFAULT_REALNAME.to_string(),
     };
-    let password = match get_var(&toml_config, "admin_password").and_then(|v| as_string(v)) {
-        Ok(n) => n,
-        _ => panic!("Bot needs an admin password!"),
-    };
-    let command_bytes = match get_var(&toml_config, "command_char").and_then(|v| as_string(v)) {
-        Ok(n) => n.into_bytes(),
+    let default_revoked_file = format!("{filename}.revoked", filename=filename);
+    let revoked_passwords_file = get_string_or(&toml_config, "revoked_passwords_file",
+                                               &default_revoked_file);
+    let revoked_passwords = read_revoked_passwords(&revoked_passwords_file);
+    let password = read_admin_password(&toml_config, &revoked_passwords);
+    let command_byte = match get_var(&toml_config, "command_char").and_then(|v| as_command_byte(v)) {
+        Ok(b) => b,
         _ => panic!("Bot needs a command_char!"),
     };
-    let command_byte = match command_bytes.get(0) {
-        Some(b) => b,
-        _ => panic!("Bot needs a command_char!"),
+    let limits = match read_limits(&toml_config, &DEFAULT_LIMITS) {
+        Ok(l) => l,
+        Err(err) => panic!("Invalid config: {err}", err=err),
     };
+    let default_version = format!("korasho {version}", version=env!("CARGO_PKG_VERSION"));
+    let ctcp_version = get_string_or(&toml_config, "ctcp_version", &default_version);
+    let ctcp_source = get_var(&toml_config, "ctcp_source").and_then(|v| as_string(v)).ok();
+    let ctcp_interval = get_integer_or(&toml_config, "ctcp_interval", DEFAULT_CTCP_INTERVAL);
+    let encodings = match read_encodings(&toml_config) {
+        Ok(Some(e)) => e,
+        Ok(None) => DEFAULT_ENCODINGS.to_vec(),
+        Err(err) => panic!("Invalid encoding: {err}", err=err),
+    };
+    let users = read_users(&toml_config);
+    let auth_idle_timeout = get_integer_or(&toml_config, "auth_idle_timeout",
+                                           DEFAULT_AUTH_IDLE_TIMEOUT);
     let toml_servers = match get_var(&toml_config, "servers").and_then(|v| as_array(v)) {
         Ok(n) => n,
         Err(err) => panic!("Config needs servers to connect to! {err}", err=err),
@@ -100,7 +231,7 @@ pub fn read_config(filename: &String) -> Config {
                 continue;
             }
         };
-        let port = match get_var(toml_server, "port").and_then(|v| as_integer(v)) {
+        let port = match get_var(toml_server, "port").and_then(|v| as_port(v)) {
             Ok(p) => p,
             Err(err) => {
                 println!("Skipping a server: {err}", err=err);
@@ -123,7 +254,52 @@ pub fn read_config(filename: &String) -> Config {
             };
             channels.push(channel);
         }
-        let port: u16 = port as u16;
+        let client_cert = get_var(toml_server, "client_cert").and_then(|v| as_string(v)).ok();
+        let client_key = get_var(toml_server, "client_key").and_then(|v| as_string(v)).ok();
+        let sasl = match read_sasl(toml_server, secure, &client_cert) {
+            Ok(s) => s,
+            Err(err) => {
+                println!("Skipping a server: {err}", err=err);
+                continue;
+            }
+        };
+        let server_command_byte = match get_var(toml_server, "command_char") {
+            Ok(v) => match as_command_byte(v) {
+                Ok(b) => b,
+                Err(err) => {
+                    println!("Skipping a server: {err}", err=err);
+                    continue;
+                }
+            },
+            _ => command_byte,
+        };
+        let server_encodings = match read_encodings(toml_server) {
+            Ok(Some(e)) => e,
+            Ok(None) => encodings.clone(),
+            Err(err) => {
+                println!("Skipping a server: {err}", err=err);
+                continue;
+            }
+        };
+        let server_limits = match read_limits(toml_server, &limits) {
+            Ok(l) => l,
+            Err(err) => {
+                println!("Skipping a server: {err}", err=err);
+                continue;
+            }
+        };
+        let mut server_users = users.clone();
+        server_users.extend(read_users(toml_server));
+        let password = get_var(toml_server, "password").and_then(|v| as_string(v)).ok();
+        let mut alternates = Vec::new();
+        if let Ok(toml_alternates) = get_var(toml_server, "alternates").and_then(|v| as_array(v)) {
+            for toml_alternate in toml_alternates {
+                match as_string(toml_alternate).and_then(|a| as_address(&a, port)) {
+                    Ok(a) => alternates.push(a),
+                    Err(err) => println!("Skipping an alternate address: {err}", err=err),
+                }
+            }
+        }
         println!("found address: {host}:{port} {secure} {channels:?}",
                  host=host,
                  port=port,
@@ -132,8 +308,32 @@ pub fn read_config(filename: &String) -> Config {
         servers.push(Server {
             host: host.clone(),
             port: port,
+            alternates: alternates,
             secure: secure,
             channels: channels,
+            nick: get_string_or(toml_server, "nick", &nick),
+            alt: get_string_or(toml_server, "alt", &alt),
+            username: get_string_or(toml_server, "username", &username),
+            realname: get_string_or(toml_server, "realname", &realname),
+            password: password,
+            command_byte: server_command_byte,
+            ping_interval: server_limits.ping_interval,
+            ping_timeout: server_limits.ping_timeout,
+            flood_burst: server_limits.flood_burst as u32,
+            flood_rate: server_limits.flood_rate as u64,
+            max_lines: server_limits.max_lines as usize,
+            ctcp_version: get_string_or(toml_server, "ctcp_version", &ctcp_version),
+            ctcp_source: match get_var(toml_server, "ctcp_source").and_then(|v| as_string(v)) {
+                Ok(s) => Some(s),
+                _ => ctcp_source.clone(),
+            },
+            ctcp_interval: get_integer_or(toml_server, "ctcp_interval", ctcp_interval),
+            encodings: server_encodings,
+            users: server_users,
+            auth_idle_timeout: get_integer_or(toml_server, "auth_idle_timeout", auth_idle_timeout),
+            sasl: sasl,
+            client_cert: client_cert,
+            client_key: client_key,
         })
     }
     Config {
@@ -143,7 +343,286 @@ pub fn read_config(filename: &String) -> Config {
         username: username,
         realname: realname,
         admin_password: password,
-        command_byte: *command_byte,
+        admin_password_revoked: Arc::new(AtomicBool::new(false)),
+        revoked_passwords_file: revoked_passwords_file,
+        command_byte: command_byte,
+        ping_interval: limits.ping_interval,
+        ping_timeout: limits.ping_timeout,
+        flood_burst: limits.flood_burst as u32,
+        flood_rate: limits.flood_rate as u64,
+        max_lines: limits.max_lines as usize,
+        ctcp_version: ctcp_version,
+        ctcp_source: ctcp_source,
+        ctcp_interval: ctcp_interval,
+        encodings: encodings,
+        users: users,
+        auth_idle_timeout: auth_idle_timeout,
+    }
+}
+
+fn read_sasl(toml_server: &Value, secure: bool,
+             client_cert: &Option<String>) -> Result<Option<Sasl>, String> {
+    let mechanism = match get_var(toml_server, "sasl_mechanism").and_then(|v| as_string(v)) {
+        Ok(m) => m,
+        _ => return Ok(None),
+    };
+    let mechanism = match &*mechanism.to_uppercase() {
+        "PLAIN" => SaslMechanism::Plain,
+        "EXTERNAL" => SaslMechanism::External,
+        _ => return Err(format!("Unsupported sasl_mechanism {mechanism}", mechanism=mechanism)),
+    };
+    let account = get_var(toml_server, "sasl_account").and_then(|v| as_string(v));
+    let password = get_var(toml_server, "sasl_password").and_then(|v| as_string(v));
+    let required = match get_var(toml_server, "sasl_required").and_then(|v| as_bool(v)) {
+        Ok(r) => r,
+        _ => true,
+    };
+    match mechanism {
+        SaslMechanism::Plain => {
+            let account = match account {
+                Ok(a) => a,
+                Err(err) => return Err(format!("SASL PLAIN needs a sasl_account: {err}", err=err)),
+            };
+            let password = match password {
+                Ok(p) => p,
+                Err(err) => return Err(format!("SASL PLAIN needs a sasl_password: {err}", err=err)),
+            };
+            Ok(Some(Sasl {
+                mechanism: mechanism,
+                account: account,
+                password: password,
+                required: required,
+            }))
+        },
+        SaslMechanism::External => {
+            if !secure || client_cert.is_none() {
+                return Err(format!("SASL EXTERNAL needs secure = true and a client_cert."));
+            }
+            Ok(Some(Sasl {
+                mechanism: mechanism,
+                account: account.unwrap_or("".to_string()),
+                password: "".to_string(),
+                required: required,
+            }))
+        },
+    }
+}
+
+fn read_revoked_passwords(filename: &str) -> Vec<::password::PasswordHash> {
+    let mut input = String::new();
+    match File::open(filename).and_then(|mut f| f.read_to_string(&mut input)) {
+        Ok(_) => ::password::parse_list(&input),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn read_admin_password(map: &Value,
+                       revoked: &[::password::PasswordHash]) -> ::password::PasswordHash {
+    if let Ok(hash) = get_var(map, "admin_password_hash").and_then(|v| as_string(v)) {
+        let hash = match ::password::parse(&hash) {
+            Ok(h) => h,
+            Err(err) => panic!("Invalid admin_password_hash: {err}", err=err),
+        };
+        if revoked.contains(&hash) {
+            panic!("admin_password_hash was revoked after being sent in public. Set a new one.");
+        }
+        return hash;
+    }
+    match get_var(map, "admin_password").and_then(|v| as_string(v)) {
+        Ok(p) => {
+            println!("Warning: admin_password is stored in plain text. Run korasho \
+                      --hash-password and use admin_password_hash instead.");
+            if revoked.iter().any(|r| r.verify(&p)) {
+                panic!("admin_password was revoked after being sent in public. Set a new one.");
+            }
+            ::password::hash(&p)
+        },
+        _ => panic!("Bot needs an admin_password_hash!"),
+    }
+}
+
+fn read_users(map: &Value) -> Vec<User> {
+    let toml_users = match get_var(map, "users").and_then(|v| as_array(v)) {
+        Ok(u) => u,
+        _ => return Vec::new(),
+    };
+    let mut users = Vec::new();
+    for toml_user in toml_users {
+        let name = get_string_or(toml_user, "name", "");
+        let role = match get_var(toml_user, "role").and_then(|v| as_string(v)) {
+            Ok(r) => r,
+            Err(err) => {
+                println!("Skipping user {name}: {err}", name=name, err=err);
+                continue;
+            }
+        };
+        let role = match Role::from_name(&role) {
+            Some(r) => r,
+            None => {
+                println!("Skipping user {name}: unknown role {role}", name=name, role=role);
+                continue;
+            }
+        };
+        let masks = get_strings(toml_user, "masks");
+        let accounts = get_strings(toml_user, "accounts");
+        if masks.len() < 1 && accounts.len() < 1 {
+            println!("Skipping user {name}: needs masks or accounts", name=name);
+            continue;
+        }
+        users.push(User {
+            name: name,
+            role: role,
+            masks: masks,
+            accounts: accounts,
+        });
+    }
+    users
+}
+
+fn get_strings(map: &Value, name: &str) -> Vec<String> {
+    match get_var(map, name).and_then(|v| as_array(v)) {
+        Ok(a) => a.iter().filter_map(|v| as_string(v).ok()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn read_encodings(map: &Value) -> Result<Option<Vec<Encoding>>, String> {
+    let names = match get_var(map, "encoding") {
+        Ok(&Value::String(ref s)) => vec![s.clone()],
+        Ok(&Value::Array(ref a)) => {
+            let mut names = Vec::new();
+            for name in a {
+                match as_string(name) {
+                    Ok(n) => names.push(n),
+                    Err(err) => return Err(err),
+                }
+            }
+            names
+        },
+        Ok(_) => return Err(format!("encoding must be a string or an array of strings.")),
+        Err(_) => return Ok(None),
+    };
+    let mut encodings = Vec::new();
+    for name in names {
+        match Encoding::from_name(&name) {
+            Some(e) => encodings.push(e),
+            None => return Err(format!("Unsupported encoding {name}", name=name)),
+        }
+    }
+    if encodings.len() < 1 {
+        return Err(format!("encoding is empty."));
+    }
+    Ok(Some(encodings))
+}
+
+fn get_string_or(map: &Value, name: &str, default: &str) -> String {
+    match get_var(map, name).and_then(|v| as_string(v)) {
+        Ok(s) => s,
+        _ => default.to_string(),
+    }
+}
+
+/// The numeric settings that may be given globally and overridden per server.
+/// Every value is range checked so the casts in `read_config` cannot wrap.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+struct Limits {
+    ping_interval: i64,
+    ping_timeout: i64,
+    flood_burst: i64,
+    flood_rate: i64,
+    max_lines: i64,
+}
+
+fn read_limits(map: &Value, defaults: &Limits) -> Result<Limits, String> {
+    let unbounded = i64::max_value();
+    let ping_interval = get_integer_in(map, "ping_interval", defaults.ping_interval, 1, unbounded);
+    let ping_timeout = get_integer_in(map, "ping_timeout", defaults.ping_timeout, 1, unbounded);
+    let flood_burst = get_integer_in(map, "flood_burst", defaults.flood_burst,
+                                     1, ::std::u32::MAX as i64);
+    let flood_rate = get_integer_in(map, "flood_rate", defaults.flood_rate, 0, unbounded);
+    let max_lines = get_integer_in(map, "max_lines", defaults.max_lines, 0, unbounded);
+    match (ping_interval, ping_timeout, flood_burst, flood_rate, max_lines) {
+        (Ok(ping_interval), Ok(ping_timeout), Ok(flood_burst), Ok(flood_rate), Ok(max_lines)) => {
+            Ok(Limits {
+                ping_interval: ping_interval,
+                ping_timeout: ping_timeout,
+                flood_burst: flood_burst,
+                flood_rate: flood_rate,
+                max_lines: max_lines,
+            })
+        },
+        (Err(err), _, _, _, _) |
+        (_, Err(err), _, _, _) |
+        (_, _, Err(err), _, _) |
+        (_, _, _, Err(err), _) |
+        (_, _, _, _, Err(err)) => Err(err),
+    }
+}
+
+fn get_integer_in(map: &Value, name: &str, default: i64,
+                  min: i64, max: i64) -> Result<i64, String> {
+    let value = match get_var(map, name) {
+        Ok(v) => match as_integer(v) {
+            Ok(i) => i,
+            Err(err) => return Err(format!("{name}: {err}", name=name, err=err)),
+        },
+        _ => return Ok(default),
+    };
+    match value < min || value > max {
+        true => Err(format!("{name} must be between {min} and {max}, not {value}",
+                            name=name, min=min, max=max, value=value)),
+        false => Ok(value),
+    }
+}
+
+fn get_integer_or(map: &Value, name: &str, default: i64) -> i64 {
+    match get_var(map, name).and_then(|v| as_integer(v)) {
+        Ok(i) => i,
+        _ => default,
+    }
+}
+
+fn as_address(address: &str, default_port: u16) -> Result<Address, String> {
+    let mut address_iter = address.rsplitn(2, ':');
+    let last = address_iter.next().unwrap_or("");
+    let (host, port) = match address_iter.next() {
+        Some(h) => match last.parse::<u16>() {
+            Ok(0) => return Err(format!("Invalid port in {address}", address=address)),
+            Ok(p) => (h, p),
+            Err(_) => return Err(format!("Invalid port in {address}", address=address)),
+        },
+        None => (last, default_port),
+    };
+    if host.len() < 1 {
+        return Err(format!("Invalid address {address}", address=address));
+    }
+    Ok(Address {
+        host: host.to_string(),
+        port: port,
+    })
+}
+
+fn as_port(value: &Value) -> Result<u16, String> {
+    let port = match as_integer(value) {
+        Ok(p) => p,
+        Err(err) => return Err(format!("Invalid port: {err}", err=err)),
+    };
+    match port < 1 || port > ::std::u16::MAX as i64 {
+        true => Err(format!("Invalid port {port}", port=port)),
+        false => Ok(port as u16),
+    }
+}
+
+fn as_command_byte(value: &Value) -> Result<u8, String> {
+    let command_bytes = match as_string(value) {
+        Ok(s) => s.into_bytes(),
+        Err(err) => return Err(err),
+    };
+    match command_bytes.get(0) {
+        Some(b) => Ok(*b),
+        _ => Err(format!("command_char is empty.")),
     }
 }
 
@@ -182,3 +661,87 @@ fn get_var<'a>(map: &'a Value, name: &str) -> Result<&'a Value, String> {
     };
     map.get(name).ok_or_else(|| format!("{name} not found", name=name))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_config, as_address, Config, DEFAULT_LIMITS};
+
+    const HASH: &'static str = "pbkdf2-sha1$100000$454f803b76b85b818e0773315f499b86\
+                                $8f4005a75e8b9795466a4f639a4e544ab822886a";
+
+    fn config(globals: &str, server: &str) -> Config {
+        let input = format!("command_char = \"!\"\n\
+                             admin_password_hash = \"{hash}\"\n\
+                             {globals}\n\
+                             [[servers]]\n\
+                             host = \"irc.example.org\"\n\
+                             channels = [\"#korasho\"]\n\
+                             {server}\n",
+                            hash=HASH, globals=globals, server=server);
+        parse_config("/nonexistent/korasho.toml", &input)
+    }
+
+    #[test]
+    fn defaults_apply_to_servers() {
+        let config = config("", "port = 6697");
+        assert_eq!(config.servers.len(), 1);
+        let server = &config.servers[0];
+        assert_eq!(server.port, 6697);
+        assert_eq!(server.ping_interval, DEFAULT_LIMITS.ping_interval);
+        assert_eq!(server.flood_burst, DEFAULT_LIMITS.flood_burst as u32);
+        assert_eq!(server.max_lines, DEFAULT_LIMITS.max_lines as usize);
+    }
+
+    #[test]
+    fn servers_override_globals() {
+        let config = config("flood_burst = 3\nping_interval = 30",
+                            "port = 6667\nflood_burst = 8\nflood_rate = 0");
+        assert_eq!(config.flood_burst, 3);
+        let server = &config.servers[0];
+        assert_eq!(server.flood_burst, 8);
+        assert_eq!(server.flood_rate, 0);
+        assert_eq!(server.ping_interval, 30);
+    }
+
+    #[test]
+    fn invalid_ports_skip_the_server() {
+        for port in &["0", "-1", "65536", "\"6667\""] {
+            let config = config("", &format!("port = {port}", port=port));
+            assert_eq!(config.servers.len(), 0, "port = {}", port);
+        }
+        assert_eq!(config("", "port = 65535").servers[0].port, 65535);
+    }
+
+    #[test]
+    fn invalid_server_limits_skip_the_server() {
+        for setting in &["flood_burst = 0", "flood_burst = -1", "flood_burst = 4294967296",
+                         "ping_interval = 0", "ping_interval = -120", "ping_timeout = 0",
+                         "flood_rate = -1", "max_lines = -1"] {
+            let config = config("", &format!("port = 6667\n{setting}", setting=setting));
+            assert_eq!(config.servers.len(), 0, "{}", setting);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "flood_burst must be between")]
+    fn negative_global_flood_burst_is_rejected() {
+        config("flood_burst = -5", "port = 6667");
+    }
+
+    #[test]
+    #[should_panic(expected = "ping_interval must be between")]
+    fn zero_global_ping_interval_is_rejected() {
+        config("ping_interval = 0", "port = 6667");
+    }
+
+    #[test]
+    fn alternate_addresses() {
+        let address = as_address("irc.example.net:7000", 6667).unwrap();
+        assert_eq!((address.host.as_str(), address.port), ("irc.example.net", 7000));
+        let address = as_address("irc.example.net", 6667).unwrap();
+        assert_eq!((address.host.as_str(), address.port), ("irc.example.net", 6667));
+        assert!(as_address("irc.example.net:0", 6667).is_err());
+        assert!(as_address("irc.example.net:70000", 6667).is_err());
+        assert!(as_address(":7000", 6667).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use openssl::ssl::{Ssl, SslContext, SslMethod, SslStream};
+use openssl::x509::X509FileType;
 use std::net::TcpStream;
 use std::io::prelude::*;
 use std::time::Duration;
@@ -45,19 +46,22 @@ pub struct Connection {
 }
 
 impl Connection {
-    pub fn write(&mut self, buf: &[u8]) {
+    pub fn write(&mut self, buf: &[u8]) -> io::Result<()> {
         println!(" -> {:?}", String::from_utf8_lossy(buf));
-        self.writer.write_all(buf).unwrap();
-        self.writer.flush().unwrap();
+        match self.writer.write_all(buf) {
+            Ok(_) => self.writer.flush(),
+            Err(err) => Err(err),
+        }
     }
     pub fn read(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
         self.reader.read_until(b'\n', buf)
     }
 }
 
-pub fn connect(server: ::config::Server) -> Result<Connection, String> {
-    println!("Connecting to: {server}", server=server);
-    let address = format!("{host}:{port}", host=server.host, port=server.port).to_string();
+pub fn connect(server: ::config::Server,
+               address: &::config::Address) -> Result<Connection, String> {
+    println!("Connecting to: {address}", address=address);
+    let address = address.to_string();
 
     let stream = match TcpStream::connect(&*address) {
         Ok(c) => c,
@@ -68,10 +72,25 @@ pub fn connect(server: ::config::Server) -> Result<Connection, String> {
     stream.set_write_timeout(timeout).unwrap();
 
     if server.secure {
-        let context = match SslContext::new(SslMethod::Sslv23) {
+        let mut context = match SslContext::new(SslMethod::Sslv23) {
             Ok(c) => c,
             Err(err) => return Err(format!("Unable to make an SSL context: {err}", err=err)),
         };
+        if let Some(ref cert) = server.client_cert {
+            let key = match server.client_key {
+                Some(ref k) => k.clone(),
+                None => cert.clone(),
+            };
+            if let Err(err) = context.set_certificate_file(cert, X509FileType::PEM) {
+                return Err(format!("Unable to load client certificate {cert}: {err}", cert=cert, err=err));
+            }
+            if let Err(err) = context.set_private_key_file(&key, X509FileType::PEM) {
+                return Err(format!("Unable to load client key {key}: {err}", key=key, err=err));
+            }
+            if let Err(err) = context.check_private_key() {
+                return Err(format!("Client certificate and key do not match: {err}", err=err));
+            }
+        }
         let ssl = match Ssl::new(&context) {
             Ok(c) => c,
             Err(err) => return Err(format!("Unable to make an SSL object: {err}", err=err)),
@@ -0,0 +1,86 @@
+use std::borrow::Cow;
+
+pub const DELIMITER: u8 = 0x01;
+
+pub const ACTION: &'static str = "ACTION";
+pub const CLIENTINFO: &'static str = "CLIENTINFO";
+pub const PING: &'static str = "PING";
+pub const SOURCE: &'static str = "SOURCE";
+pub const TIME: &'static str = "TIME";
+pub const VERSION: &'static str = "VERSION";
+
+pub fn is_ctcp(text: &[u8]) -> bool {
+    text.first() == Some(&DELIMITER)
+}
+
+pub fn parse<'a>(text: &'a str) -> Option<(Cow<'a, str>, &'a str)> {
+    if !is_ctcp(text.as_bytes()) {
+        return None;
+    }
+    let mut body = &text[1..];
+    if body.ends_with(DELIMITER as char) {
+        body = &body[..body.len() - 1];
+    }
+    let mut body_iter = body.splitn(2, ' ');
+    let command = body_iter.next().unwrap_or("");
+    if command.len() < 1 {
+        return None;
+    }
+    let command = match command.bytes().any(|b| b.is_ascii_lowercase()) {
+        true => Cow::Owned(command.to_ascii_uppercase()),
+        false => Cow::Borrowed(command),
+    };
+    Some((command, body_iter.next().unwrap_or("")))
+}
+
+pub fn encode(command: &str, args: &str) -> String {
+    let delimiter = DELIMITER as char;
+    let args: String = args.chars().filter(|c| *c != delimiter).collect();
+    if args.len() < 1 {
+        return format!("{d}{command}{d}", d=delimiter, command=command);
+    }
+    format!("{d}{command} {args}{d}", d=delimiter, command=command, args=args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, is_ctcp, parse};
+
+    fn parsed(text: &str) -> Option<(String, String)> {
+        parse(text).map(|(command, args)| (command.into_owned(), args.to_string()))
+    }
+
+    #[test]
+    fn commands_and_arguments() {
+        assert_eq!(parsed("\x01VERSION\x01"), Some(("VERSION".to_string(), "".to_string())));
+        assert_eq!(parsed("\x01ping 1234 5678\x01"),
+                   Some(("PING".to_string(), "1234 5678".to_string())));
+        assert_eq!(parsed("\x01ACTION waves \x01"),
+                   Some(("ACTION".to_string(), "waves ".to_string())));
+    }
+
+    #[test]
+    fn missing_closing_delimiter() {
+        assert_eq!(parsed("\x01ACTION waves"), Some(("ACTION".to_string(), "waves".to_string())));
+        assert_eq!(parsed("\x01TIME"), Some(("TIME".to_string(), "".to_string())));
+    }
+
+    #[test]
+    fn empty_command_or_plain_text() {
+        assert_eq!(parse("\x01\x01"), None);
+        assert_eq!(parse("\x01"), None);
+        assert_eq!(parse("\x01 VERSION\x01"), None);
+        assert_eq!(parse("hello \x01VERSION\x01"), None);
+        assert_eq!(parse(""), None);
+        assert!(!is_ctcp(b"VERSION"));
+    }
+
+    #[test]
+    fn encoding_strips_delimiters() {
+        assert_eq!(encode("VERSION", ""), "\x01VERSION\x01");
+        assert_eq!(encode("ACTION", "waves \x01back\x01"), "\x01ACTION waves back\x01");
+        assert_eq!(encode("PING", "\x01"), "\x01PING\x01");
+        let encoded = encode("ACTION", "dances");
+        assert_eq!(parsed(&encoded), Some(("ACTION".to_string(), "dances".to_string())));
+    }
+}
@@ -0,0 +1,149 @@
+use std::str;
+use std::borrow::Cow;
+
+const CP1252_HIGH: [Option<char>; 32] = [
+    Some('\u{20ac}'), None, Some('\u{201a}'), Some('\u{0192}'),
+    Some('\u{201e}'), Some('\u{2026}'), Some('\u{2020}'), Some('\u{2021}'),
+    Some('\u{02c6}'), Some('\u{2030}'), Some('\u{0160}'), Some('\u{2039}'),
+    Some('\u{0152}'), None, Some('\u{017d}'), None,
+    None, Some('\u{2018}'), Some('\u{2019}'), Some('\u{201c}'),
+    Some('\u{201d}'), Some('\u{2022}'), Some('\u{2013}'), Some('\u{2014}'),
+    Some('\u{02dc}'), Some('\u{2122}'), Some('\u{0161}'), Some('\u{203a}'),
+    Some('\u{0153}'), None, Some('\u{017e}'), Some('\u{0178}'),
+];
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Cp1252,
+}
+
+pub const DEFAULT_ENCODINGS: [Encoding; 3] = [Encoding::Utf8, Encoding::Cp1252, Encoding::Latin1];
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match &*name.to_lowercase().replace('_', "-") {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "cp1252" | "windows-1252" => Some(Encoding::Cp1252),
+            _ => None,
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Option<String> {
+        match *self {
+            Encoding::Utf8 => str::from_utf8(bytes).ok().map(|s| s.to_string()),
+            Encoding::Latin1 => Some(bytes.iter().map(|b| *b as char).collect()),
+            Encoding::Cp1252 => {
+                let mut text = String::with_capacity(bytes.len());
+                for byte in bytes {
+                    match *byte {
+                        0x80..=0x9f => match CP1252_HIGH[(*byte - 0x80) as usize] {
+                            Some(c) => text.push(c),
+                            None => return None,
+                        },
+                        b => text.push(b as char),
+                    }
+                }
+                Some(text)
+            },
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match *self {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            Encoding::Latin1 => text.chars().map(|c| match c as u32 {
+                0..=0xff => c as u8,
+                _ => b'?',
+            }).collect(),
+            Encoding::Cp1252 => text.chars().map(|c| match c as u32 {
+                0x80..=0x9f => b'?',
+                0..=0xff => c as u8,
+                _ => match CP1252_HIGH.iter().position(|h| *h == Some(c)) {
+                    Some(i) => 0x80 + i as u8,
+                    None => b'?',
+                },
+            }).collect(),
+        }
+    }
+}
+
+pub fn decode<'a>(encodings: &[Encoding], bytes: &'a [u8]) -> Cow<'a, str> {
+    for encoding in encodings {
+        if *encoding == Encoding::Utf8 {
+            match str::from_utf8(bytes) {
+                Ok(text) => return Cow::Borrowed(text),
+                Err(_) => continue,
+            }
+        }
+        if let Some(text) = encoding.decode(bytes) {
+            return Cow::Owned(text);
+        }
+    }
+    String::from_utf8_lossy(bytes)
+}
+
+pub fn encode(encodings: &[Encoding], text: &str) -> Vec<u8> {
+    match encodings.first() {
+        Some(e) => e.encode(text),
+        None => text.as_bytes().to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use super::{decode, encode, Encoding, DEFAULT_ENCODINGS};
+
+    #[test]
+    fn valid_utf8_is_borrowed() {
+        match decode(&DEFAULT_ENCODINGS, "héllo ☃".as_bytes()) {
+            Cow::Borrowed(text) => assert_eq!(text, "héllo ☃"),
+            Cow::Owned(_) => panic!("UTF-8 input should not be copied"),
+        }
+    }
+
+    #[test]
+    fn falls_back_through_the_chain() {
+        assert_eq!(decode(&DEFAULT_ENCODINGS, b"caf\xe9"), "café");
+        assert_eq!(decode(&DEFAULT_ENCODINGS, b"\x93hi\x94 \x80"), "\u{201c}hi\u{201d} \u{20ac}");
+        assert_eq!(decode(&DEFAULT_ENCODINGS, b"\x81"), "\u{81}");
+        assert_eq!(decode(&[Encoding::Utf8, Encoding::Latin1], b"\x93"), "\u{93}");
+    }
+
+    #[test]
+    fn lossy_when_nothing_matches() {
+        assert_eq!(decode(&[Encoding::Utf8, Encoding::Cp1252], b"a\x81b"), "a\u{fffd}b");
+        assert_eq!(decode(&[], b"a\xffb"), "a\u{fffd}b");
+    }
+
+    #[test]
+    fn encodes_with_the_first_encoding() {
+        assert_eq!(encode(&[Encoding::Latin1], "café ☃"), b"caf\xe9 ?".to_vec());
+        assert_eq!(encode(&[Encoding::Cp1252, Encoding::Utf8], "€ \u{201c}x\u{201d} \u{85}"),
+                   b"\x80 \x93x\x94 ?".to_vec());
+        assert_eq!(encode(&[Encoding::Utf8], "é"), "é".as_bytes().to_vec());
+        assert_eq!(encode(&[], "é"), "é".as_bytes().to_vec());
+    }
+
+    #[test]
+    fn cp1252_round_trips() {
+        let undefined = [0x81, 0x8d, 0x8f, 0x90, 0x9d];
+        let bytes: Vec<u8> = (0x20u8..=0xff).filter(|b| !undefined.contains(b)).collect();
+        let text = Encoding::Cp1252.decode(&bytes).unwrap();
+        assert_eq!(Encoding::Cp1252.encode(&text), bytes);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Encoding::from_name("UTF-8"), Some(Encoding::Utf8));
+        assert_eq!(Encoding::from_name("iso_8859_1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::from_name("Windows-1252"), Some(Encoding::Cp1252));
+        assert_eq!(Encoding::from_name("koi8-r"), None);
+    }
+}
@@ -0,0 +1,253 @@
+use irc::{ConnectionState, IRCMessageType, IRCServerMessage};
+use bot::BotJob;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum EventType {
+    Any,
+    Connected,
+    Disconnected,
+    Tick,
+    Message,
+    Action,
+    Notice,
+    Join,
+    Part,
+    Kick,
+    Quit,
+    Nick,
+    Topic,
+    Mode,
+    Invite,
+    Numeric,
+    Other,
+}
+
+#[derive(Debug)]
+pub struct Event<'a> {
+    pub kind: EventType,
+    pub message: Option<&'a IRCServerMessage<'a>>,
+    pub channel: Option<&'a str>,
+    pub reason: Option<&'a str>,
+    pub time: i64,
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+pub struct Outcome {
+    pub jobs: Vec<BotJob>,
+    pub stop: bool,
+}
+
+impl Outcome {
+    pub fn jobs(jobs: Vec<BotJob>) -> Outcome {
+        Outcome {
+            jobs: jobs,
+            stop: false,
+        }
+    }
+
+    pub fn stop(jobs: Vec<BotJob>) -> Outcome {
+        Outcome {
+            jobs: jobs,
+            stop: true,
+        }
+    }
+}
+
+pub trait Hook {
+    fn handle(&self, event: &Event, conn_state: &ConnectionState) -> Outcome;
+}
+
+pub struct Subscription {
+    pub kind: EventType,
+    pub channel: Option<String>,
+    pub network: Option<String>,
+    pub hook: Box<dyn Hook>,
+}
+
+impl Subscription {
+    pub fn in_channel(mut self, channel: &str) -> Subscription {
+        self.channel = Some(channel.to_string());
+        self
+    }
+
+    pub fn on_network(mut self, network: &str) -> Subscription {
+        self.network = Some(network.to_string());
+        self
+    }
+
+    pub fn matches(&self, event: &Event, networks: &[&str], conn_state: &ConnectionState) -> bool {
+        if self.kind != EventType::Any && self.kind != event.kind {
+            return false;
+        }
+        if let Some(ref network) = self.network {
+            if !networks.iter().any(|n| n.eq_ignore_ascii_case(network)) {
+                return false;
+            }
+        }
+        match (&self.channel, event.channel) {
+            (&Some(ref wanted), Some(channel)) => conn_state.isupport.casemapping.equals(wanted, channel),
+            (&Some(_), None) => false,
+            (&None, _) => true,
+        }
+    }
+}
+
+pub fn subscribe(kind: EventType, hook: Box<dyn Hook>) -> Subscription {
+    Subscription {
+        kind: kind,
+        channel: None,
+        network: None,
+        hook: hook,
+    }
+}
+
+pub fn new<'a>(kind: EventType, time: i64) -> Event<'a> {
+    Event {
+        kind: kind,
+        message: None,
+        channel: None,
+        reason: None,
+        time: time,
+    }
+}
+
+pub fn from_message<'a>(message: &'a IRCServerMessage<'a>,
+                        conn_state: &ConnectionState) -> Event<'a> {
+    let (kind, channel) = match message.message {
+        IRCMessageType::PRIVMSG(_) | IRCMessageType::CTCP(_) => (EventType::Message, None),
+        IRCMessageType::ACTION(_) => (EventType::Action, None),
+        IRCMessageType::NOTICE(_) | IRCMessageType::CTCPREPLY(_) => (EventType::Notice, None),
+        IRCMessageType::JOIN((c, _)) => (EventType::Join, Some(c)),
+        IRCMessageType::PART((c, _)) => (EventType::Part, Some(c)),
+        IRCMessageType::KICK((c, _, _)) => (EventType::Kick, Some(c)),
+        IRCMessageType::TOPIC((c, _)) => (EventType::Topic, Some(c)),
+        IRCMessageType::INVITE((_, c)) => (EventType::Invite, Some(c)),
+        IRCMessageType::QUIT(_) => (EventType::Quit, None),
+        IRCMessageType::NICK(_) => (EventType::Nick, None),
+        IRCMessageType::MODE(_) => (EventType::Mode, None),
+        IRCMessageType::NUMERIC(_) => (EventType::Numeric, None),
+        _ => (EventType::Other, None),
+    };
+    let channel = match channel {
+        Some(c) => Some(c),
+        None if conn_state.isupport.is_channel(message.target) => Some(message.target),
+        None => None,
+    };
+    Event {
+        kind: kind,
+        message: Some(message),
+        channel: channel,
+        reason: None,
+        time: message.time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use bot::BotJob;
+    use irc::{ConnectionState, IRCMessage};
+    use plugin::{Plugin, Registry};
+    use super::{from_message, subscribe, EventType, Event, Hook, Outcome, Subscription};
+
+    type Log = Rc<RefCell<Vec<&'static str>>>;
+
+    struct Recorder {
+        name: &'static str,
+        stop: bool,
+        log: Log,
+    }
+
+    impl Hook for Recorder {
+        fn handle(&self, _: &Event, _: &ConnectionState) -> Outcome {
+            self.log.borrow_mut().push(self.name);
+            let jobs = vec![BotJob::PrivMsg(("#rust".to_string(), self.name.to_string()))];
+            match self.stop {
+                true => Outcome::stop(jobs),
+                false => Outcome::jobs(jobs),
+            }
+        }
+    }
+
+    struct Hooks {
+        log: Log,
+    }
+
+    impl Hooks {
+        fn hook(&self, kind: EventType, name: &'static str, stop: bool) -> Subscription {
+            subscribe(kind, Box::new(Recorder { name: name, stop: stop, log: self.log.clone() }))
+        }
+    }
+
+    impl Plugin for Hooks {
+        fn name(&self) -> &'static str {
+            "hooks"
+        }
+
+        fn subscriptions(&self) -> Vec<Subscription> {
+            vec![self.hook(EventType::Join, "elsewhere", true).in_channel("#python"),
+                 self.hook(EventType::Join, "other network", true).on_network("example"),
+                 self.hook(EventType::Message, "messages", true),
+                 self.hook(EventType::Any, "any", false),
+                 self.hook(EventType::Join, "rust", true).in_channel("#RUST").on_network("libera"),
+                 self.hook(EventType::Any, "after stop", false)]
+        }
+    }
+
+    fn dispatch(line: &str, registry: &Registry, log: &Log) -> (Vec<&'static str>, Outcome) {
+        let conn_state = ConnectionState::default();
+        let message = match ::irc_parser::parse_line(line.as_bytes(), line) {
+            Some(IRCMessage::IRCServerMessage(m)) => m,
+            other => panic!("{:?} didn't parse: {:?}", line, other),
+        };
+        let event = from_message(&message, &conn_state);
+        let outcome = registry.dispatch_event(&event, &["Libera", "irc.libera.chat"], &conn_state);
+        (log.replace(Vec::new()), outcome)
+    }
+
+    #[test]
+    fn channel_events_carry_the_channel() {
+        let conn_state = ConnectionState::default();
+        let lines = [(":a!b@c JOIN #rust", EventType::Join, Some("#rust")),
+                     (":a!b@c KICK #rust bob :bye", EventType::Kick, Some("#rust")),
+                     (":a!b@c PRIVMSG #rust :hi", EventType::Message, Some("#rust")),
+                     (":a!b@c PRIVMSG bot :hi", EventType::Message, None),
+                     (":a!b@c INVITE bot #rust", EventType::Invite, Some("#rust")),
+                     (":a!b@c QUIT :gone", EventType::Quit, None)];
+        for &(line, kind, channel) in lines.iter() {
+            let message = match ::irc_parser::parse_line(line.as_bytes(), line) {
+                Some(IRCMessage::IRCServerMessage(m)) => m,
+                other => panic!("{:?} didn't parse: {:?}", line, other),
+            };
+            let event = from_message(&message, &conn_state);
+            assert_eq!(event.kind, kind, "{:?}", line);
+            assert_eq!(event.channel, channel, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn filters_and_stop() {
+        let log = Log::default();
+        let mut registry = Registry::default();
+        registry.register(Box::new(Hooks { log: log.clone() }));
+
+        let (ran, outcome) = dispatch(":a!b@c JOIN #rust", &registry, &log);
+        assert_eq!(ran, vec!["any", "rust"]);
+        assert!(outcome.stop);
+        assert_eq!(outcome.jobs.len(), 2);
+
+        let (ran, outcome) = dispatch(":a!b@c JOIN #go", &registry, &log);
+        assert_eq!(ran, vec!["any", "after stop"]);
+        assert!(!outcome.stop);
+
+        let (ran, outcome) = dispatch(":a!b@c PRIVMSG #rust :hi", &registry, &log);
+        assert_eq!(ran, vec!["messages"]);
+        assert!(outcome.stop);
+        assert_eq!(outcome.jobs.len(), 1);
+    }
+}
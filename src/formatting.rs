@@ -0,0 +1,344 @@
+pub const BOLD: u8 = 0x02;
+pub const COLOR: u8 = 0x03;
+pub const HEX_COLOR: u8 = 0x04;
+pub const RESET: u8 = 0x0f;
+pub const MONOSPACE: u8 = 0x11;
+pub const REVERSE: u8 = 0x16;
+pub const ITALIC: u8 = 0x1d;
+pub const STRIKETHROUGH: u8 = 0x1e;
+pub const UNDERLINE: u8 = 0x1f;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum Color {
+    Index(u8),
+    Rgb(String),
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub monospace: bool,
+    pub reverse: bool,
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+pub fn is_code(byte: u8) -> bool {
+    match byte {
+        BOLD | COLOR | HEX_COLOR | RESET | MONOSPACE | REVERSE | ITALIC | STRIKETHROUGH |
+        UNDERLINE => true,
+        _ => false,
+    }
+}
+
+pub fn code_len(bytes: &[u8]) -> Option<usize> {
+    match bytes[0] {
+        COLOR => Some(1 + color_args_len(&bytes[1..], 2, |b| b.is_ascii_digit())),
+        HEX_COLOR => Some(1 + color_args_len(&bytes[1..], 6, |b| b.is_ascii_hexdigit())),
+        b if is_code(b) => Some(1),
+        _ => None,
+    }
+}
+
+fn color_args_len<F>(bytes: &[u8], max: usize, is_digit: F) -> usize where F: Fn(&u8) -> bool {
+    let foreground = bytes.iter().take(max).take_while(|b| is_digit(b)).count();
+    if foreground < 1 || bytes.get(foreground) != Some(&b',') {
+        return foreground;
+    }
+    let background = bytes[foreground + 1..].iter().take(max).take_while(|b| is_digit(b)).count();
+    if background < 1 {
+        return foreground;
+    }
+    foreground + 1 + background
+}
+
+pub fn strip(text: &str) -> String {
+    parse(text).into_iter().map(|s| s.text).collect()
+}
+
+pub fn parse(text: &str) -> Vec<Span> {
+    let bytes = text.as_bytes();
+    let mut spans = Vec::new();
+    let mut style = Style::default();
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let len = match code_len(&bytes[i..]) {
+            Some(l) => l,
+            None => {
+                i += 1;
+                continue;
+            },
+        };
+        if i > start {
+            spans.push(Span {
+                text: text[start..i].to_string(),
+                style: style.clone(),
+            });
+        }
+        apply_code(&mut style, &text[i..i + len]);
+        i += len;
+        start = i;
+    }
+    if start < bytes.len() {
+        spans.push(Span {
+            text: text[start..].to_string(),
+            style: style,
+        });
+    }
+    spans
+}
+
+pub fn apply_codes(style: &mut Style, text: &str) {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match code_len(&bytes[i..]) {
+            Some(len) => {
+                apply_code(style, &text[i..i + len]);
+                i += len;
+            },
+            None => i += 1,
+        }
+    }
+}
+
+fn apply_code(style: &mut Style, code: &str) {
+    let bytes = code.as_bytes();
+    match bytes[0] {
+        BOLD => style.bold = !style.bold,
+        ITALIC => style.italic = !style.italic,
+        UNDERLINE => style.underline = !style.underline,
+        STRIKETHROUGH => style.strikethrough = !style.strikethrough,
+        MONOSPACE => style.monospace = !style.monospace,
+        REVERSE => style.reverse = !style.reverse,
+        RESET => *style = Style::default(),
+        COLOR | HEX_COLOR => {
+            let args = &code[1..];
+            if args.len() < 1 {
+                style.foreground = None;
+                style.background = None;
+                return;
+            }
+            let mut args_iter = args.splitn(2, ',');
+            let hex = bytes[0] == HEX_COLOR;
+            style.foreground = args_iter.next().and_then(|c| parse_color(c, hex));
+            if let Some(background) = args_iter.next() {
+                style.background = parse_color(background, hex);
+            }
+        },
+        _ => {},
+    }
+}
+
+fn parse_color(color: &str, hex: bool) -> Option<Color> {
+    if hex {
+        return Some(Color::Rgb(color.to_uppercase()));
+    }
+    color.parse::<u8>().ok().map(|c| Color::Index(c))
+}
+
+fn format_color(color: &Color) -> String {
+    match *color {
+        Color::Index(i) => format!("{i:02}", i=i),
+        Color::Rgb(ref rgb) => rgb.clone(),
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct Formatter {
+    spans: Vec<Span>,
+}
+
+impl Formatter {
+    pub fn text(mut self, text: &str) -> Formatter {
+        self.spans.push(Span {
+            text: text.to_string(),
+            style: Style::default(),
+        });
+        self
+    }
+
+    pub fn styled(mut self, text: &str, style: Style) -> Formatter {
+        self.spans.push(Span {
+            text: text.to_string(),
+            style: style,
+        });
+        self
+    }
+
+    pub fn bold(self, text: &str) -> Formatter {
+        self.styled(text, Style { bold: true, ..Style::default() })
+    }
+
+    pub fn italic(self, text: &str) -> Formatter {
+        self.styled(text, Style { italic: true, ..Style::default() })
+    }
+
+    pub fn underline(self, text: &str) -> Formatter {
+        self.styled(text, Style { underline: true, ..Style::default() })
+    }
+
+    pub fn color(self, text: &str, foreground: Color, background: Option<Color>) -> Formatter {
+        self.styled(text, Style {
+            foreground: Some(foreground),
+            background: background,
+            ..Style::default()
+        })
+    }
+
+    pub fn plain(&self) -> String {
+        self.spans.iter().map(|s| strip(&s.text)).collect()
+    }
+
+    pub fn build(&self) -> String {
+        let mut line = String::new();
+        for span in self.spans.iter() {
+            let text = strip(&span.text);
+            if text.len() < 1 {
+                continue;
+            }
+            let codes = opening_codes(&span.style, &text);
+            let styled = codes.len() > 0;
+            line.push_str(&codes);
+            line.push_str(&text);
+            if styled {
+                line.push(RESET as char);
+            }
+        }
+        line
+    }
+}
+
+pub fn opening_codes(style: &Style, text: &str) -> String {
+    let mut codes = style_codes(style);
+    if text.starts_with(',') && style.foreground.is_some() && style.background.is_none() {
+        codes.push(BOLD as char);
+        codes.push(BOLD as char);
+    }
+    codes
+}
+
+fn style_codes(style: &Style) -> String {
+    let mut codes = String::new();
+    let flags = [
+        (style.bold, BOLD),
+        (style.italic, ITALIC),
+        (style.underline, UNDERLINE),
+        (style.strikethrough, STRIKETHROUGH),
+        (style.monospace, MONOSPACE),
+        (style.reverse, REVERSE),
+    ];
+    for &(set, code) in flags.iter() {
+        if set {
+            codes.push(code as char);
+        }
+    }
+    if let Some(ref foreground) = style.foreground {
+        let code = match *foreground {
+            Color::Index(_) => COLOR,
+            Color::Rgb(_) => HEX_COLOR,
+        };
+        codes.push(code as char);
+        codes.push_str(&format_color(foreground));
+        if let Some(ref background) = style.background {
+            codes.push(',');
+            codes.push_str(&format_color(background));
+        }
+    }
+    codes
+}
+
+pub fn new() -> Formatter {
+    Formatter::default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_codes, code_len, new, parse, strip, Color, Span, Style};
+
+    #[test]
+    fn code_lengths() {
+        assert_eq!(code_len(b"\x02bold"), Some(1));
+        assert_eq!(code_len(b"\x03"), Some(1));
+        assert_eq!(code_len(b"\x034text"), Some(2));
+        assert_eq!(code_len(b"\x03123"), Some(3));
+        assert_eq!(code_len(b"\x0304,12x"), Some(6));
+        assert_eq!(code_len(b"\x0304,x"), Some(3));
+        assert_eq!(code_len(b"\x03,04"), Some(1));
+        assert_eq!(code_len(b"\x04FF00aa,000000"), Some(14));
+        assert_eq!(code_len(b"plain"), None);
+    }
+
+    #[test]
+    fn strip_removes_every_code() {
+        assert_eq!(strip("\x02bold\x02 \x1ditalic\x0f \x0304,12red\x03 \x04ff0000hex \x1funder"),
+                   "bold italic red hex under");
+        assert_eq!(strip("\x0304,text"), ",text");
+        assert_eq!(strip("1\x03 2,3"), "1 2,3");
+        assert_eq!(strip("caf\u{e9} \u{1f980}"), "caf\u{e9} \u{1f980}");
+    }
+
+    #[test]
+    fn parse_tracks_styles() {
+        let spans = parse("a\x02b\x0304,01c\x03d\x0fe");
+        let bold = Style { bold: true, ..Style::default() };
+        let red = Style {
+            foreground: Some(Color::Index(4)),
+            background: Some(Color::Index(1)),
+            ..bold.clone()
+        };
+        let expected = [("a", Style::default()), ("b", bold.clone()), ("c", red),
+                        ("d", bold), ("e", Style::default())];
+        let expected: Vec<Span> = expected.iter()
+            .map(|&(text, ref style)| Span { text: text.to_string(), style: style.clone() })
+            .collect();
+        assert_eq!(spans, expected);
+    }
+
+    #[test]
+    fn colour_changes_keep_the_background() {
+        let mut style = Style::default();
+        apply_codes(&mut style, "\x0304,01\x0305");
+        assert_eq!(style.foreground, Some(Color::Index(5)));
+        assert_eq!(style.background, Some(Color::Index(1)));
+        apply_codes(&mut style, "\x04ff8800");
+        assert_eq!(style.foreground, Some(Color::Rgb("FF8800".to_string())));
+    }
+
+    #[test]
+    fn formatter_round_trips_through_parse() {
+        let line = new()
+            .text("plain ")
+            .bold("bold")
+            .color(",comma", Color::Index(4), None)
+            .color("both", Color::Index(12), Some(Color::Index(1)))
+            .text(" \x02stripped\x02")
+            .build();
+        assert_eq!(line, "plain \x02bold\x0f\x0304\x02\x02,comma\x0f\x0312,01both\x0f stripped");
+        assert_eq!(strip(&line), "plain bold,commaboth stripped");
+        let styled: Vec<(String, Option<Color>)> = parse(&line).into_iter()
+            .filter(|s| s.text.len() > 0)
+            .map(|s| (s.text, s.style.foreground))
+            .collect();
+        assert_eq!(styled[2], (",comma".to_string(), Some(Color::Index(4))));
+    }
+}
@@ -1,12 +1,30 @@
-use std::str;
+use std::borrow::Cow;
+use std::io;
+use std::mem;
+use std::collections::{BTreeMap, HashMap};
 use time;
+use numeric::Numeric;
 
 #[derive(Default)]
 #[derive(Debug)]
 pub struct ConnectionState {
     pub nick: String,
     pub server_address: String,
+    pub caps: ::capabilities::Capabilities,
+    pub account: Option<String>,
+    pub sasl_error: Option<String>,
+    pub registered: bool,
+    pub lag_ms: Option<u64>,
+    pub hostmask: Option<Client<'static>>,
+    pub channels: ::channel::Channels,
+    pub isupport: ::isupport::ISupport,
+    pub batches: HashMap<String, String>,
     identified: bool,
+    sasl_in_progress: bool,
+    nick_attempts: usize,
+    last_nick_attempt: i64,
+    pending_ping: Option<(String, u64)>,
+    more: HashMap<::casemap::IRCKey, Vec<String>>,
 }
 
 #[derive(Debug)]
@@ -15,108 +33,262 @@ pub struct IRC<'a> {
     config: &'a ::config::Config,
     conn_state: ConnectionState,
     bot: ::bot::Bot<'a>,
+    send_queue: ::send_queue::SendQueue,
+    read_buf: Vec<u8>,
+    last_read: i64,
+    disconnect_reason: Option<String>,
 }
 
 #[derive(Debug)]
 #[derive(Clone)]
 #[derive(PartialEq)]
-pub struct Client {
-    pub nick: String,
-    pub username: String,
-    pub address: String,
+pub struct Client<'a> {
+    pub nick: Cow<'a, str>,
+    pub username: Cow<'a, str>,
+    pub address: Cow<'a, str>,
+}
+
+impl<'a> Client<'a> {
+    pub fn into_owned(self) -> Client<'static> {
+        Client {
+            nick: Cow::Owned(self.nick.into_owned()),
+            username: Cow::Owned(self.username.into_owned()),
+            address: Cow::Owned(self.address.into_owned()),
+        }
+    }
 }
 
 #[derive(Debug)]
 #[derive(Clone)]
-pub enum Entity {
-    Server(String),
-    Client(Client),
+pub enum Entity<'a> {
+    Server(Cow<'a, str>),
+    Client(Client<'a>),
 }
 
+/// The typed view of a line. Payloads borrow from the line being processed;
+/// anything kept past it (sessions, channel state, bot jobs) is copied out.
 #[derive(Debug)]
 #[derive(Clone)]
-pub enum IRCMessageType {
-    NOTICE(String),
-    MODE(String),
-    PRIVMSG(Vec<u8>),
-    INFO(u64),
+pub enum IRCMessageType<'a> {
+    NOTICE(&'a str),
+    MODE(Vec<&'a str>),
+    PRIVMSG(&'a str),
+    ACTION(&'a str),
+    CTCP((Cow<'a, str>, &'a str)),
+    CTCPREPLY((Cow<'a, str>, &'a str)),
+    CAP((Cow<'a, str>, Vec<&'a str>)),
+    AUTHENTICATE(&'a str),
+    NICK(&'a str),
+    PONG(&'a str),
+    JOIN((&'a str, Option<&'a str>)),
+    PART((&'a str, &'a str)),
+    KICK((&'a str, &'a str, &'a str)),
+    QUIT(&'a str),
+    TOPIC((&'a str, &'a str)),
+    INVITE((&'a str, &'a str)),
+    ERROR(&'a str),
+    KILL((&'a str, &'a str)),
+    WALLOPS(&'a str),
+    AWAY(Option<&'a str>),
+    ACCOUNT(Option<&'a str>),
+    CHGHOST((&'a str, &'a str)),
+    BATCH((&'a str, &'a str)),
+    NUMERIC(::numeric::Numeric),
+    UNKNOWN((Cow<'a, str>, Vec<&'a str>)),
 }
 
+pub type IRCTags = BTreeMap<String, String>;
+
 #[derive(Debug)]
 #[derive(Clone)]
-pub struct IRCServerMessage {
-    pub from: Entity,
-    pub message: IRCMessageType,
+pub struct IRCServerMessage<'a> {
+    pub line: ::message::Message<'a>,
+    pub raw_bytes: &'a [u8],
+    pub from: Option<Entity<'a>>,
+    pub message: IRCMessageType<'a>,
     pub time: i64,
-    pub raw: String,
-    pub target: String,
+    pub target: &'a str,
 }
 
 #[derive(Debug)]
 #[derive(Clone)]
-pub struct IRCPing {
+pub struct IRCPing<'a> {
     pub time: i64,
+    pub token: &'a str,
 }
 
 #[derive(Debug)]
 #[derive(Clone)]
-pub enum IRCMessage {
-    IRCServerMessage(IRCServerMessage),
-    IRCPing(IRCPing),
+pub enum IRCMessage<'a> {
+    IRCServerMessage(IRCServerMessage<'a>),
+    IRCPing(IRCPing<'a>),
 }
 
+const AUTHENTICATE: &'static str = "AUTHENTICATE";
+const CAP: &'static str = "CAP";
 const NICK: &'static str = "NICK";
+const PASS: &'static str = "PASS";
+const PING: &'static str = "PING";
 const JOIN: &'static str = "JOIN";
+const MODE: &'static str = "MODE";
+const MONITOR: &'static str = "MONITOR";
 const USER: &'static str = "USER";
 const PONG: &'static str = "PONG";
+const QUIT: &'static str = "QUIT";
 const PRIVMSG: &'static str = "PRIVMSG";
+const NICK_REGAIN_INTERVAL: i64 = 60;
+const MAX_NICK_ATTEMPTS: usize = 10;
+const NOTICE: &'static str = "NOTICE";
+
+const MAX_LINE_LENGTH: usize = 512;
 
 impl<'a> IRC<'a> {
-    pub fn run (&mut self) {
+    pub fn run(&mut self) -> Result<(), String> {
+        let result = self.run_session();
+        let reason = match result {
+            Ok(_) => "Connection closed".to_string(),
+            Err(ref err) => err.clone(),
+        };
+        self.bot.disconnected(&reason, &self.conn_state);
+        result
+    }
+
+    fn run_session(&mut self) -> Result<(), String> {
+        self.last_read = time::get_time().sec;
+        self.identify();
         loop {
-            let mut buf: Vec<u8> = Vec::new();
-            if let Ok(_) = self.connection.read(&mut buf) {
-                if let Ok(result_str) = str::from_utf8(&buf) {
-                    self.process_line(&result_str);
-                }
+            match self.read() {
+                Ok(true) => return Ok(()),
+                Ok(false) => {},
+                Err(err) => return Err(err),
+            }
+            while let Some(job) = self.bot.get_job(&self.conn_state) {
+                self.handle_bot_job(job);
+            }
+            self.tick();
+            self.flush_send_queue();
+            if let Some(reason) = self.disconnect_reason.take() {
+                return Err(reason);
             }
-            let job = self.bot.get_job();
-            self.handle_bot_job(job);
         }
     }
 
-    fn handle_bot_job(&mut self, bot_job: Option<::bot::BotJob>) {
-        let bot_job = match bot_job {
-            Some(j) => j,
-            None => return,
+    pub fn registered(&self) -> bool {
+        self.conn_state.registered
+    }
+
+    pub fn into_bot(self) -> ::bot::Bot<'a> {
+        self.bot
+    }
+
+    fn read(&mut self) -> Result<bool, String> {
+        match self.connection.read(&mut self.read_buf) {
+            Ok(0) => return Ok(true),
+            Ok(_) => {},
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                            err.kind() == io::ErrorKind::TimedOut => return Ok(false),
+            Err(err) => return Err(format!("Read error: {err}", err=err)),
+        }
+        if self.read_buf.last() != Some(&b'\n') {
+            return Ok(false);
+        }
+        self.last_read = time::get_time().sec;
+        let mut buf = mem::replace(&mut self.read_buf, Vec::new());
+        self.process_line(&buf);
+        buf.clear();
+        self.read_buf = buf;
+        Ok(false)
+    }
+
+    fn tick(&mut self) {
+        let now = time::get_time().sec;
+        self.bot.tick(now, &self.conn_state);
+        self.check_ping(now);
+        if !self.conn_state.registered || self.conn_state.isupport.monitor.is_some() {
+            return;
+        }
+        if now - self.conn_state.last_nick_attempt < NICK_REGAIN_INTERVAL {
+            return;
+        }
+        self.regain_nick();
+    }
+
+    fn check_ping(&mut self, now: i64) {
+        let idle = now - self.last_read;
+        if let Some((_, sent)) = self.conn_state.pending_ping {
+            let waited = (time::precise_time_ns() - sent) / 1000000000;
+            if waited as i64 >= self.connection.server.ping_timeout {
+                self.disconnect_reason = Some(format!("Ping timeout: {seconds} seconds",
+                                                      seconds=waited));
+            }
+            return;
+        }
+        if idle < self.connection.server.ping_interval {
+            return;
+        }
+        let sent = time::precise_time_ns();
+        let token = format!("korasho-{sent}", sent=sent);
+        self.send_command(PING, &[&token]);
+        self.conn_state.pending_ping = Some((token, sent));
+    }
+
+    fn handle_pong(&mut self, token: &str) {
+        let sent = match self.conn_state.pending_ping {
+            Some((ref t, sent)) if t == token => sent,
+            _ => return,
         };
+        let lag_ms = (time::precise_time_ns() - sent) / 1000000;
+        self.conn_state.lag_ms = Some(lag_ms);
+        self.conn_state.pending_ping = None;
+        println!("Lag to {server}: {lag} ms", server=self.connection.server, lag=lag_ms);
+    }
+
+    fn flush_send_queue(&mut self) {
+        while let Some(line) = self.send_queue.pop() {
+            self.send_raw(&line);
+        }
+    }
+
+    fn handle_bot_job(&mut self, bot_job: ::bot::BotJob) {
         match bot_job {
             ::bot::BotJob::Join(channel) => self.join(&channel),
             ::bot::BotJob::PrivMsg((nick, message)) => {
-                self.priv_msg(&nick, &message);
+                self.priv_msg(&BTreeMap::new(), &nick, &message);
             },
+            ::bot::BotJob::TaggedPrivMsg((tags, nick, message)) => {
+                self.priv_msg(&tags, &nick, &message);
+            },
+            ::bot::BotJob::Action((target, action)) => self.action(&target, &action),
+            ::bot::BotJob::Notice((target, message)) => {
+                let message = ::irc_serializer::sanitize(&message);
+                self.send_command(NOTICE, &[&target, &message]);
+            },
+            ::bot::BotJob::More(target) => self.more(&target),
         }
     }
 
-    fn process_line(&mut self, line: &str) {
+    fn process_line(&mut self, line: &[u8]) {
+        let line = ::irc_parser::trim_line(line);
         if line.len() < 1 {
             return;
         }
-        let line = line.to_string();
-        let debug = line.clone();
-        let message = match ::irc_parser::parse_line(line) {
+        let text = ::encoding::decode(&self.connection.server.encodings, line);
+        let message = match ::irc_parser::parse_line(line, &text) {
             Some(m) => m,
             None => {
-                println!("Err parsing: {}", debug);
+                println!("Err parsing: {}", text);
                 return;
             },
         };
-        let bot_message = message.clone();
         match message {
-            IRCMessage::IRCServerMessage(m) => self.process_server_message(m),
-            IRCMessage::IRCPing(p) => self.handle_ping(p),
+            IRCMessage::IRCServerMessage(ref m) => {
+                self.process_server_message(m);
+                if !self.is_echo(m) {
+                    self.bot.process_server_message(m, &self.conn_state);
+                }
+            },
+            IRCMessage::IRCPing(ref p) => self.handle_ping(p),
         }
-        self.bot.handle_message(bot_message, &self.conn_state);
     }
 
     fn format_time(&mut self, seconds: i64) -> String {
@@ -128,61 +300,515 @@ impl<'a> IRC<'a> {
         }
     }
 
-    fn handle_ping(&mut self, ping: IRCPing) {
+    fn handle_ping(&mut self, ping: &IRCPing) {
         let time = self.format_time(ping.time);
-        println!("<- {time} PING {server}", time=time, server=self.conn_state.server_address);
-        let server_address = self.conn_state.server_address.clone();
-        self.send_command(PONG, &server_address);
-    }
-
-    fn process_server_message(&mut self, message: IRCServerMessage) {
-        println!("<- {time} {line}", time=self.format_time(message.time), line=message.raw);
-        if !self.conn_state.identified {
-            match message.from {
-                Entity::Server(s) => {
-                    self.conn_state.server_address = s;
-                    self.identify();
-                    self.conn_state.identified = true;
-                },
-                _ => {},
-            }
+        println!("<- {time} PING {token}", time=time, token=ping.token);
+        self.send_command(PONG, &[ping.token]);
+    }
+
+    fn process_server_message(&mut self, message: &IRCServerMessage) {
+        println!("<- {time} {line}", time=self.format_time(message.time), line=message.line.raw);
+        match message.from {
+            Some(Entity::Server(ref s)) if self.conn_state.server_address.len() < 1 => {
+                self.conn_state.server_address = s.to_string();
+            },
+            Some(Entity::Client(ref c)) => self.update_hostmask(c),
+            _ => {},
+        }
+        match message.message {
+            IRCMessageType::CAP((ref subcommand, ref args)) => self.handle_cap(subcommand, args),
+            IRCMessageType::AUTHENTICATE(ref data) => self.handle_authenticate(data),
+            IRCMessageType::NICK(ref nick) => {
+                if let Some(Entity::Client(ref c)) = message.from {
+                    self.conn_state.channels.rename(&c.nick, nick);
+                }
+                self.handle_nick(&message.from, nick);
+            },
+            IRCMessageType::JOIN((ref channel, ref account)) => {
+                if let Some(Entity::Client(ref c)) = message.from {
+                    let ours = self.is_own_nick(&c.nick);
+                    let account = account.map(|a| a.to_string());
+                    self.conn_state.channels.join(channel, c, account, ours);
+                    if ours {
+                        self.send_command(MODE, &[channel]);
+                    }
+                }
+            },
+            IRCMessageType::PART((ref channel, _)) => {
+                if let Some(Entity::Client(ref c)) = message.from {
+                    let ours = self.is_own_nick(&c.nick);
+                    self.conn_state.channels.part(channel, &c.nick, ours);
+                }
+            },
+            IRCMessageType::KICK((ref channel, ref nick, _)) => {
+                let ours = self.is_own_nick(nick);
+                self.conn_state.channels.part(channel, nick, ours);
+            },
+            IRCMessageType::QUIT(_) => {
+                if let Some(Entity::Client(ref c)) = message.from {
+                    self.conn_state.channels.quit(&c.nick);
+                }
+            },
+            IRCMessageType::KILL((ref nick, ref reason)) => {
+                if self.is_own_nick(nick) {
+                    self.disconnect_reason = Some(format!("Killed: {reason}", reason=reason));
+                }
+                self.conn_state.channels.quit(nick);
+            },
+            IRCMessageType::ERROR(ref reason) => {
+                if self.disconnect_reason.is_none() {
+                    self.disconnect_reason = Some(format!("Server error: {reason}", reason=reason));
+                }
+            },
+            IRCMessageType::ACCOUNT(ref account) => {
+                if let Some(Entity::Client(ref c)) = message.from {
+                    let account = account.map(|a| a.to_string());
+                    self.conn_state.channels.set_account(&c.nick, account.clone());
+                    if self.is_own_nick(&c.nick) {
+                        self.conn_state.account = account;
+                    }
+                }
+            },
+            IRCMessageType::CHGHOST((ref username, ref address)) => {
+                if let Some(Entity::Client(ref c)) = message.from {
+                    self.conn_state.channels.set_host(&c.nick, username, address);
+                    let client = Client {
+                        nick: c.nick.clone(),
+                        username: Cow::Borrowed(username),
+                        address: Cow::Borrowed(address),
+                    };
+                    self.update_hostmask(&client);
+                }
+            },
+            IRCMessageType::TOPIC((ref channel, ref topic)) => {
+                let setter = match message.from {
+                    Some(Entity::Client(ref c)) => c.nick.clone(),
+                    Some(Entity::Server(ref s)) => s.clone(),
+                    None => Cow::Borrowed(""),
+                };
+                self.conn_state.channels.set_topic(channel, Some(topic), Some(&setter), Some(message.time));
+            },
+            IRCMessageType::MODE(_) => {
+                if self.conn_state.isupport.is_channel(message.target) {
+                    self.conn_state.channels.apply_modes(&self.conn_state.isupport, message.target,
+                                                         &message.line.params()[1..], false);
+                }
+            },
+            IRCMessageType::BATCH((ref reference, ref kind)) => {
+                if reference.starts_with('+') {
+                    self.conn_state.batches.insert(reference[1..].to_string(), kind.to_string());
+                } else if reference.starts_with('-') {
+                    self.conn_state.batches.remove(&reference[1..]);
+                }
+            },
+            IRCMessageType::PONG(ref token) => self.handle_pong(token),
+            IRCMessageType::NUMERIC(ref numeric) => self.handle_numeric(numeric),
+            _ => {},
         }
     }
 
+    fn handle_numeric(&mut self, numeric: &Numeric) {
+        match *numeric {
+            Numeric::Welcome((ref nick, ref text)) => {
+                self.handle_welcome(nick);
+                self.update_hostmask_from(text.split(' ').last().unwrap_or(""));
+            },
+            Numeric::ISupport(ref tokens) => {
+                self.conn_state.isupport.update(tokens);
+                self.conn_state.channels.set_casemapping(self.conn_state.isupport.casemapping);
+            },
+            Numeric::ChannelModeIs((ref channel, ref modes)) => {
+                self.conn_state.channels.apply_modes(&self.conn_state.isupport, channel, modes, true);
+            },
+            Numeric::Topic((ref channel, ref topic)) => {
+                self.conn_state.channels.set_topic(channel, Some(topic), None, None);
+            },
+            Numeric::TopicWhoTime((ref channel, ref setter, time)) => {
+                self.conn_state.channels.set_topic(channel, None, Some(setter), Some(time));
+            },
+            Numeric::NamReply((_, ref channel, ref names)) => {
+                self.conn_state.channels.add_names(&self.conn_state.isupport, channel, names);
+            },
+            Numeric::EndOfNames(ref channel) => self.conn_state.channels.end_names(channel),
+            Numeric::HostHidden(ref host) => {
+                if let Some(mut hostmask) = self.conn_state.hostmask.clone() {
+                    hostmask.address = Cow::Owned(host.clone());
+                    self.conn_state.hostmask = Some(hostmask);
+                }
+            },
+            Numeric::NicknameInUse(_) | Numeric::NickCollision(_) |
+            Numeric::UnavailableResource(_) => self.handle_nick_collision(),
+            Numeric::MonOffline(ref targets) => {
+                let offline = targets.iter()
+                    .any(|t| self.is_primary_nick(t.split('!').next().unwrap_or("")));
+                if offline {
+                    self.regain_nick();
+                }
+            },
+            Numeric::LoggedIn((_, ref account, _)) => self.conn_state.account = Some(account.clone()),
+            Numeric::LoggedOut(_) => self.conn_state.account = None,
+            Numeric::SaslSuccess(_) | Numeric::SaslAlready(_) => self.finish_sasl(None),
+            Numeric::NickLocked(ref reason) => self.fail_sasl(902, reason),
+            Numeric::SaslFail(ref reason) => self.fail_sasl(904, reason),
+            Numeric::SaslTooLong(ref reason) => self.fail_sasl(905, reason),
+            Numeric::SaslAborted(ref reason) => self.fail_sasl(906, reason),
+            _ => {},
+        }
+    }
+
+    fn fail_sasl(&mut self, code: u16, reason: &str) {
+        let error = format!("SASL authentication failed ({code}): {reason}",
+                            code=code,
+                            reason=reason);
+        self.finish_sasl(Some(error));
+    }
+
     fn identify(&mut self) {
-        self.nick();
+        if self.conn_state.identified {
+            return;
+        }
+        self.conn_state.caps.negotiating = true;
+        self.send_command(CAP, &["LS", "302"]);
+        if let Some(password) = self.connection.server.password.clone() {
+            self.send_command(PASS, &[&password]);
+        }
+        let nick = self.connection.server.nick.clone();
+        self.nick(&nick);
         self.user();
+        self.conn_state.identified = true;
+    }
+
+    fn handle_welcome(&mut self, nick: &str) {
+        self.conn_state.caps.negotiating = false;
+        self.conn_state.registered = true;
+        self.conn_state.nick = nick.to_string();
+        let sasl_required = match self.connection.server.sasl {
+            Some(ref s) => s.required,
+            None => false,
+        };
+        if sasl_required && self.conn_state.account.is_none() && self.disconnect_reason.is_none() {
+            let error = "SASL authentication failed: registered before SASL completed".to_string();
+            println!("{server}: {error}", server=self.connection.server, error=error);
+            self.send_command(QUIT, &[&error]);
+            self.disconnect_reason = Some(error);
+            return;
+        }
+        if self.is_primary_nick(nick) || self.conn_state.isupport.monitor.is_none() {
+            return;
+        }
+        let nick = self.connection.server.nick.clone();
+        self.send_command(MONITOR, &["+", &nick]);
+    }
+
+    fn handle_nick(&mut self, from: &Option<Entity>, nick: &str) {
+        let old_nick = match from {
+            &Some(Entity::Client(ref c)) => c.nick.clone(),
+            _ => return,
+        };
+        if !self.is_own_nick(&old_nick) {
+            return;
+        }
+        println!("Nick changed from {old} to {new}", old=old_nick, new=nick);
+        self.conn_state.nick = nick.to_string();
+        if self.is_primary_nick(nick) && self.conn_state.isupport.monitor.is_some() {
+            self.send_command(MONITOR, &["-", nick]);
+        }
+    }
+
+    fn handle_nick_collision(&mut self) {
+        if self.conn_state.registered {
+            return;
+        }
+        self.conn_state.nick_attempts += 1;
+        if self.conn_state.nick_attempts > MAX_NICK_ATTEMPTS {
+            self.disconnect_reason = Some(format!("No free nick after {attempts} attempts",
+                                                  attempts=MAX_NICK_ATTEMPTS));
+            return;
+        }
+        let nick = match self.conn_state.nick_attempts {
+            1 => self.connection.server.alt.clone(),
+            n => generated_nick(&self.connection.server.nick, n - 1,
+                                self.conn_state.isupport.nicklen),
+        };
+        println!("Nick {old} is taken, trying {new}", old=self.conn_state.nick, new=nick);
+        self.nick(&nick);
+    }
+
+    fn is_echo(&self, message: &IRCServerMessage) -> bool {
+        if !self.conn_state.caps.is_enabled(::capabilities::ECHO_MESSAGE) {
+            return false;
+        }
+        let echoed = ["PRIVMSG", "NOTICE", "TAGMSG"].iter().any(|c| message.line.is_command(c));
+        match message.from {
+            Some(Entity::Client(ref c)) => echoed && self.is_own_nick(&c.nick),
+            _ => false,
+        }
+    }
+
+    fn is_own_nick(&self, nick: &str) -> bool {
+        self.conn_state.isupport.casemapping.equals(&self.conn_state.nick, nick)
+    }
+
+    fn is_primary_nick(&self, nick: &str) -> bool {
+        self.conn_state.isupport.casemapping.equals(&self.connection.server.nick, nick)
+    }
+
+    fn regain_nick(&mut self) {
+        self.conn_state.last_nick_attempt = time::get_time().sec;
+        let current = self.conn_state.nick.clone();
+        if self.is_primary_nick(&current) {
+            return;
+        }
+        let nick = self.connection.server.nick.clone();
+        self.send_command(NICK, &[&nick]);
+    }
+
+    fn handle_cap(&mut self, subcommand: &str, args: &[&str]) {
+        let (more, list) = match args.len() {
+            0 => return,
+            1 => (false, args[0]),
+            _ => (args[0] == "*", args[1]),
+        };
+        match subcommand {
+            "LS" => {
+                self.conn_state.caps.add_available(&list);
+                if !more {
+                    self.request_caps();
+                }
+            },
+            "NEW" => {
+                self.conn_state.caps.add_available(&list);
+                self.request_caps();
+            },
+            "DEL" => self.conn_state.caps.remove_available(&list),
+            "ACK" => {
+                self.conn_state.caps.acknowledge(&list);
+                println!("Enabled capabilities: {caps:?}", caps=self.conn_state.caps.enabled);
+                self.start_sasl();
+                self.end_cap_negotiation();
+            },
+            "NAK" => {
+                println!("Capabilities rejected: {list}", list=list);
+                self.conn_state.caps.reject(&list);
+                self.end_cap_negotiation();
+            },
+            _ => {},
+        }
+    }
+
+    fn request_caps(&mut self) {
+        let wanted = self.bot.capabilities();
+        let request = self.conn_state.caps.to_request(&wanted);
+        if request.len() < 1 {
+            self.end_cap_negotiation();
+            return;
+        }
+        for cap in request.iter() {
+            self.conn_state.caps.requested.insert(cap.clone());
+        }
+        self.send_command(CAP, &["REQ", &request.join(" ")]);
+    }
+
+    fn end_cap_negotiation(&mut self) {
+        if !self.conn_state.caps.negotiating || self.conn_state.caps.requested.len() > 0 {
+            return;
+        }
+        if self.conn_state.sasl_in_progress {
+            return;
+        }
+        if self.sasl_unavailable() {
+            let error = "SASL authentication failed: server does not offer SASL".to_string();
+            self.finish_sasl(Some(error));
+            return;
+        }
+        self.conn_state.caps.negotiating = false;
+        self.send_command(CAP, &["END"]);
+    }
+
+    fn start_sasl(&mut self) {
+        if !self.conn_state.caps.negotiating || self.conn_state.sasl_in_progress {
+            return;
+        }
+        if !self.conn_state.caps.is_enabled(::sasl::SASL) || self.conn_state.account.is_some() {
+            return;
+        }
+        let mechanism = match self.connection.server.sasl {
+            Some(ref s) => s.mechanism.clone(),
+            None => return,
+        };
+        if !::sasl::supports_mechanism(self.conn_state.caps.value(::sasl::SASL), &mechanism) {
+            let error = format!("SASL authentication failed: server does not support {mechanism}",
+                                mechanism=mechanism);
+            self.finish_sasl(Some(error));
+            return;
+        }
+        self.conn_state.sasl_in_progress = true;
+        self.send_command(AUTHENTICATE, &[&mechanism.to_string()]);
+    }
+
+    fn handle_authenticate(&mut self, data: &str) {
+        if !self.conn_state.sasl_in_progress || data != "+" {
+            return;
+        }
+        let messages = match self.connection.server.sasl {
+            Some(ref s) => ::sasl::authenticate_messages(s),
+            None => return,
+        };
+        for message in messages {
+            self.send_command(AUTHENTICATE, &[&message]);
+        }
+    }
+
+    fn sasl_unavailable(&self) -> bool {
+        self.connection.server.sasl.is_some() && self.conn_state.account.is_none() &&
+            self.conn_state.sasl_error.is_none() && !self.conn_state.caps.is_enabled(::sasl::SASL)
+    }
+
+    fn finish_sasl(&mut self, error: Option<String>) {
+        match error {
+            Some(ref e) => println!("{server}: {error}", server=self.connection.server, error=e),
+            None => println!("{server}: SASL authentication succeeded", server=self.connection.server),
+        }
+        let required = match self.connection.server.sasl {
+            Some(ref s) => s.required,
+            None => false,
+        };
+        self.conn_state.sasl_error = error.clone();
+        self.conn_state.sasl_in_progress = false;
+        if let Some(error) = error {
+            if required {
+                self.send_command(QUIT, &[&error]);
+                self.disconnect_reason = Some(error);
+                return;
+            }
+        }
+        self.end_cap_negotiation();
+    }
+
+    fn update_hostmask(&mut self, client: &Client) {
+        if client.address.len() < 1 || !self.is_own_nick(&client.nick) {
+            return;
+        }
+        self.conn_state.hostmask = Some(client.clone().into_owned());
     }
 
-    fn priv_msg(&mut self, target: &str, message: &str) {
-        let message = format!("{target} :{message}", target=target, message=message);
-        self.send_command(PRIVMSG, &message);
+    fn update_hostmask_from(&mut self, mask: &str) {
+        if let Some(Entity::Client(ref c)) = ::irc_parser::parse_from(mask) {
+            self.update_hostmask(c);
+        }
+    }
+
+    fn message_budget(&self, command: &str, target: &str) -> usize {
+        let prefix_len = match self.conn_state.hostmask {
+            Some(ref h) => h.nick.len() + h.username.len() + h.address.len() + 4,
+            None => {
+                self.conn_state.nick.len() + self.connection.server.username.len() +
+                    self.conn_state.isupport.hostlen + 5
+            },
+        };
+        let overhead = prefix_len + command.len() + target.len() + 5;
+        if overhead >= MAX_LINE_LENGTH {
+            return 1;
+        }
+        MAX_LINE_LENGTH - overhead
+    }
+
+    fn priv_msg(&mut self, tags: &IRCTags, target: &str, message: &str) {
+        let message = &::irc_serializer::sanitize(message);
+        let budget = self.message_budget(PRIVMSG, target);
+        let mut lines = ::split::split_message(message, budget);
+        let max_lines = self.connection.server.max_lines;
+        if max_lines > 0 && lines.len() > max_lines {
+            let budget = budget.saturating_sub(::split::MORE_SUFFIX.len());
+            lines = ::split::split_message(message, budget);
+        }
+        let target_key = self.conn_state.isupport.casemapping.key(target);
+        self.conn_state.more.remove(&target_key);
+        self.send_pages(tags, target, lines);
+    }
+
+    fn action(&mut self, target: &str, action: &str) {
+        let action = ::irc_serializer::sanitize(action);
+        let overhead = ::ctcp::ACTION.len() + 3;
+        let budget = self.message_budget(PRIVMSG, target).saturating_sub(overhead);
+        for line in ::split::split_message(&action, budget) {
+            let line = ::ctcp::encode(::ctcp::ACTION, &line);
+            self.send_command(PRIVMSG, &[target, &line]);
+        }
+    }
+
+    fn more(&mut self, target: &str) {
+        let target_key = self.conn_state.isupport.casemapping.key(target);
+        let lines = match self.conn_state.more.remove(&target_key) {
+            Some(l) => l,
+            None => return,
+        };
+        self.send_pages(&BTreeMap::new(), target, lines);
+    }
+
+    fn send_pages(&mut self, tags: &IRCTags, target: &str, mut lines: Vec<String>) {
+        let max_lines = self.connection.server.max_lines;
+        if max_lines > 0 && lines.len() > max_lines {
+            let rest = lines.split_off(max_lines);
+            if let Some(last) = lines.last_mut() {
+                last.push_str(::split::MORE_SUFFIX);
+            }
+            let target_key = self.conn_state.isupport.casemapping.key(target);
+            self.conn_state.more.insert(target_key, rest);
+        }
+        for line in lines {
+            self.send_tagged_command(tags, PRIVMSG, &[target, &line]);
+        }
     }
 
     fn join(&mut self, channel: &str) {
-        self.send_command(JOIN, channel);
+        self.send_command(JOIN, &[channel]);
     }
 
-    fn nick(&mut self) {
-        let nick = self.config.nick.clone();
-        self.send_command(NICK, &nick);
-        self.conn_state.nick = nick;
+    fn nick(&mut self, nick: &str) {
+        self.send_command(NICK, &[nick]);
+        self.conn_state.nick = nick.to_string();
     }
 
     fn user(&mut self) {
-        let user = self.config.username.clone();
-        let realname = self.config.realname.clone();
-        let message = format!("{user} 0 * :{realname}", user=user, realname=realname);
-        self.send_command(USER, &message);
+        let user = self.connection.server.username.clone();
+        let realname = self.connection.server.realname.clone();
+        self.send_command(USER, &[&user, "0", "*", &realname]);
+    }
+
+    fn send_command(&mut self, cmd: &str, params: &[&str]) {
+        self.send_tagged_command(&BTreeMap::new(), cmd, params);
     }
 
-    fn send_command(&mut self, cmd: &str, message: &str) {
-        let cmd = format!("{cmd} {message} \r\n", cmd=cmd, message=message);
-        self.send_raw(cmd.as_ref());
+    fn send_tagged_command(&mut self, tags: &IRCTags, cmd: &str, params: &[&str]) {
+        let line = match ::irc_serializer::serialize_line(tags, None, cmd, params, false) {
+            Ok(l) => l,
+            Err(e) => {
+                println!("{server}: Not sending {cmd}: {error}",
+                         server=self.connection.server,
+                         cmd=cmd,
+                         error=e);
+                return;
+            },
+        };
+        let priority = ::send_queue::priority(cmd);
+        if priority == ::send_queue::Priority::Immediate {
+            self.send_queue.spend();
+            self.send_raw(&line);
+            return;
+        }
+        let target = self.conn_state.isupport.casemapping.key(params.first().unwrap_or(&""));
+        self.send_queue.push(priority, target.as_str(), line);
     }
 
     fn send_raw(&mut self, message: &str) {
-        self.connection.write(message.as_ref());
+        if self.disconnect_reason.is_some() {
+            return;
+        }
+        let bytes = ::encoding::encode(&self.connection.server.encodings, message);
+        if let Err(err) = self.connection.write(&bytes) {
+            self.disconnect_reason = Some(format!("Write error: {err}", err=err));
+        }
     }
 
 }
@@ -192,6 +818,8 @@ pub fn new<'a>(
                 config: &'a ::config::Config,
                 bot: ::bot::Bot<'a>
         ) -> IRC<'a> {
+    let send_queue = ::send_queue::new(connection.server.flood_burst,
+                                       connection.server.flood_rate);
     IRC {
         connection: connection,
         config: config,
@@ -199,8 +827,33 @@ pub fn new<'a>(
         conn_state: ConnectionState {
             identified: false,
             ..Default::default()
-        }
+        },
+        send_queue: send_queue,
+        read_buf: Vec::new(),
+        last_read: 0,
+        disconnect_reason: None,
     }
 }
 
 
+
+fn generated_nick(nick: &str, n: usize, nicklen: usize) -> String {
+    let suffix = n.to_string();
+    let room = nicklen.saturating_sub(suffix.len());
+    let base: String = nick.chars().take(room).collect();
+    format!("{base}{suffix}", base=base, suffix=suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generated_nick;
+
+    #[test]
+    fn generated_nicks_fit_nicklen() {
+        assert_eq!(generated_nick("korasho", 1, 30), "korasho1");
+        assert_eq!(generated_nick("korasho-bot", 1, 9), "korasho-1");
+        assert_eq!(generated_nick("korasho-bot", 2, 9), "korasho-2");
+        assert_eq!(generated_nick("korasho-bot", 10, 9), "korasho10");
+        assert_eq!(generated_nick("k", 3, 1), "3");
+    }
+}
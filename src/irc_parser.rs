@@ -1,124 +1,465 @@
 use time::get_time;
+use std::borrow::Cow;
 use std::string::String;
 
-pub fn parse_line(line: String) -> Option<::irc::IRCMessage> {
-    let line_bytes: &[u8] = line.as_ref();
-    let len = line_bytes.len();
-    if len < 3 {
-        return None;
+pub fn trim_line(raw: &[u8]) -> &[u8] {
+    let mut end = raw.len();
+    while end > 0 && (raw[end - 1] == b'\n' || raw[end - 1] == b'\r') {
+        end -= 1;
     }
-    let line_bytes = &line_bytes[0..len-2];
-    if line_bytes[0] == b':' {
-        return parse_server_line(line_bytes);
-    }
-    if line_bytes[0] == b'P' && line_bytes.len() > 5 {
-        let check = String::from_utf8_lossy(line_bytes);
-        let check = &check[0..4];
-        if check == "PING" {
-            let time = get_time();
-            let ping = ::irc::IRCPing { time: time.sec };
-            return Some(::irc::IRCMessage::IRCPing(ping));
-        }
+    &raw[0..end]
+}
+
+pub fn parse_line<'a>(raw: &'a [u8], line: &'a str) -> Option<::irc::IRCMessage<'a>> {
+    match ::message::parse(line) {
+        Some(m) => to_message(m, raw),
+        None => None,
     }
-    println!("Unhandled line? {}", String::from_utf8_lossy(line_bytes));
-    return None
 }
 
-fn parse_server_line(line_bytes: &[u8]) -> Option<::irc::IRCMessage> {
-    let new_line = line_bytes.clone();
-    let (_, line_bytes) = match new_line.split_first() {
-        Some(b) => b,
-        None => return None,
-    };
-    let mut line_iter = line_bytes.splitn(2, |x| *x == b':');
-    let meta_parts = match line_iter.next() {
-        Some(p) => p,
-        None => return None,
-    };
-    let message = match line_iter.next() {
-        Some(m) => String::from_utf8_lossy(m).into_owned(),
-        None => "".to_string(),
-    };
-    let mut meta_iter = meta_parts.split(|x| *x == b' ');
-    let from = match meta_iter.next() {
-        Some(m) => m,
-        None => return None,
-    };
-    let from = match parse_from(from) {
-        Some(f) => f,
-        None => return None,
-    };
-    let server_message_type = match meta_iter.next() {
-        Some(m) => String::from_utf8_lossy(m),
-        None => return None,
-    };
-    let server_message_type = server_message_type.into_owned();
-    let target = match meta_iter.next() {
-        Some(m) => String::from_utf8_lossy(m),
-        None => return None,
-    };
-    let mut additional_data = "".to_string();
-    for next in meta_iter {
-        additional_data.push_str(&String::from_utf8_lossy(next));
+pub fn to_message<'a>(message: ::message::Message<'a>,
+                      raw_bytes: &'a [u8]) -> Option<::irc::IRCMessage<'a>> {
+    let time = get_time();
+    if message.is_command("PING") {
+        let token = message.params().last().map(|t| *t).unwrap_or("");
+        let ping = ::irc::IRCPing { time: time.sec, token: token };
+        return Some(::irc::IRCMessage::IRCPing(ping));
     }
-    let message = match make_message(&server_message_type, &message, &additional_data) {
+    let from = match message.prefix {
+        Some(p) => match parse_from(p) {
+            Some(f) => Some(f),
+            None => return None,
+        },
+        None => None,
+    };
+    let irc_message = match make_message(uppercase(message.command), message.params()) {
         Some(m) => m,
         _ => return None,
     };
-    let time = get_time();
     let server_message = ::irc::IRCServerMessage {
+        line: message,
+        raw_bytes: raw_bytes,
         from: from,
-        message: message,
+        message: irc_message,
         time: time.sec,
-        target: target.into_owned(),
-        raw: String::from_utf8_lossy(line_bytes).into_owned(),
+        target: message.param(0).unwrap_or(""),
     };
-    let irc_message = ::irc::IRCMessage::IRCServerMessage(server_message);
-    return Some(irc_message);
+    Some(::irc::IRCMessage::IRCServerMessage(server_message))
 }
 
-fn parse_from(from_bytes: &[u8]) -> Option<::irc::Entity> {
-    let mut from_iter = from_bytes.splitn(2, |x| *x == b'!');
-    let nick = match from_iter.next() {
-        Some(n) => n,
-        None => return None,
-    };
-    let nick = String::from_utf8_lossy(nick);
-    let address_bytes = match from_iter.next() {
-        Some(r) => r,
-        None => return Some(::irc::Entity::Server(nick.into_owned())),
-    };
-    let mut addresss_iter = address_bytes.splitn(2, |x| *x == b'@');
-    let username = match addresss_iter.next() {
-        Some(u) => u,
-        None => return None,
+pub fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => {},
+        }
+    }
+    unescaped
+}
+
+pub fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str("\\:"),
+            ' ' => escaped.push_str("\\s"),
+            '\\' => escaped.push_str("\\\\"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn format_tags(tags: &::irc::IRCTags) -> String {
+    if tags.len() < 1 {
+        return "".to_string();
+    }
+    let tags: Vec<String> = tags.iter().map(|(key, value)| {
+        if value.len() < 1 {
+            return key.clone();
+        }
+        format!("{key}={value}", key=key, value=escape_tag_value(value))
+    }).collect();
+    format!("@{tags} ", tags=tags.join(";"))
+}
+
+pub fn parse_from<'a>(from: &'a str) -> Option<::irc::Entity<'a>> {
+    if from.len() < 1 {
+        return None;
+    }
+    let nick_end = match from.find(|c| c == '!' || c == '@') {
+        Some(e) => e,
+        None => {
+            if from.contains('.') {
+                return Some(::irc::Entity::Server(Cow::Borrowed(from)));
+            }
+            return Some(::irc::Entity::Client(::irc::Client {
+                nick: Cow::Borrowed(from),
+                username: Cow::Borrowed(""),
+                address: Cow::Borrowed(""),
+            }));
+        },
     };
-    let address = match addresss_iter.next() {
-        Some(a) => a,
-        None => return None,
+    let address = &from[nick_end..];
+    let (username, address) = match address.find('@') {
+        Some(a) => (&address[0..a], &address[a + 1..]),
+        None => (address, ""),
     };
-    let nick = nick.into_owned();
-    let username = String::from_utf8_lossy(username).into_owned();
-    let address = String::from_utf8_lossy(address).into_owned();
     let client = ::irc::Client {
-        nick: nick,
-        username: username,
-        address: address,
+        nick: Cow::Borrowed(&from[0..nick_end]),
+        username: Cow::Borrowed(match username.starts_with('!') {
+            true => &username[1..],
+            false => username,
+        }),
+        address: Cow::Borrowed(address),
     };
     Some(::irc::Entity::Client(client))
 }
 
-fn make_message(message_type: &str, message: &str, meta: &str) -> Option<::irc::IRCMessageType> {
-    let stored_message = message.to_string();
-    match message_type {
-        "NOTICE" => Some(::irc::IRCMessageType::NOTICE(stored_message)),
-        "MODE" => Some(::irc::IRCMessageType::MODE(meta.to_string())),
-        "PRIVMSG" => Some(::irc::IRCMessageType::PRIVMSG(message.as_bytes().to_vec())),
+fn make_message<'a>(command: Cow<'a, str>,
+                    params: &[&'a str]) -> Option<::irc::IRCMessageType<'a>> {
+    let text = match params.len() {
+        0 | 1 => "",
+        _ => params[params.len() - 1],
+    };
+    match command.as_ref() {
+        "NOTICE" => {
+            if let Some(ctcp) = ::ctcp::parse(text) {
+                return Some(::irc::IRCMessageType::CTCPREPLY(ctcp));
+            }
+            Some(::irc::IRCMessageType::NOTICE(text))
+        },
+        "MODE" => Some(::irc::IRCMessageType::MODE(params.iter().skip(1).cloned().collect())),
+        "PRIVMSG" => {
+            match ::ctcp::parse(text) {
+                Some((ref command, args)) if command == ::ctcp::ACTION => {
+                    Some(::irc::IRCMessageType::ACTION(args))
+                },
+                Some(ctcp) => Some(::irc::IRCMessageType::CTCP(ctcp)),
+                None => Some(::irc::IRCMessageType::PRIVMSG(text)),
+            }
+        },
+        "CAP" => {
+            let subcommand = match params.get(1) {
+                Some(s) => uppercase(s),
+                None => return None,
+            };
+            let args = params.iter().skip(2).cloned().collect();
+            Some(::irc::IRCMessageType::CAP((subcommand, args)))
+        },
+        "AUTHENTICATE" => {
+            let data = match params.first() {
+                Some(d) => d,
+                None => return None,
+            };
+            Some(::irc::IRCMessageType::AUTHENTICATE(data))
+        },
+        "NICK" => {
+            let nick = match params.first() {
+                Some(n) => n,
+                None => return None,
+            };
+            Some(::irc::IRCMessageType::NICK(nick))
+        },
+        "JOIN" => {
+            let channel = match params.first() {
+                Some(c) => c,
+                None => return None,
+            };
+            let account = match params.get(1) {
+                Some(a) if *a != "*" => Some(*a),
+                _ => None,
+            };
+            Some(::irc::IRCMessageType::JOIN((channel, account)))
+        },
+        "PART" => {
+            let channel = match params.first() {
+                Some(c) => c,
+                None => return None,
+            };
+            Some(::irc::IRCMessageType::PART((channel, text)))
+        },
+        "KICK" => {
+            if params.len() < 2 {
+                return None;
+            }
+            let reason = params.get(2).map(|r| *r).unwrap_or("");
+            Some(::irc::IRCMessageType::KICK((params[0], params[1], reason)))
+        },
+        "QUIT" => Some(::irc::IRCMessageType::QUIT(params.first().map(|r| *r).unwrap_or(""))),
+        "TOPIC" => {
+            if params.len() < 2 {
+                return None;
+            }
+            Some(::irc::IRCMessageType::TOPIC((params[0], params[1])))
+        },
+        "PONG" => Some(::irc::IRCMessageType::PONG(params.last().map(|t| *t).unwrap_or(""))),
+        "INVITE" => {
+            if params.len() < 2 {
+                return None;
+            }
+            Some(::irc::IRCMessageType::INVITE((params[0], params[1])))
+        },
+        "ERROR" => Some(::irc::IRCMessageType::ERROR(params.first().map(|r| *r).unwrap_or(""))),
+        "KILL" => {
+            let nick = match params.first() {
+                Some(n) => n,
+                None => return None,
+            };
+            Some(::irc::IRCMessageType::KILL((nick, text)))
+        },
+        "WALLOPS" => {
+            Some(::irc::IRCMessageType::WALLOPS(params.first().map(|t| *t).unwrap_or("")))
+        },
+        "AWAY" => Some(::irc::IRCMessageType::AWAY(params.first().map(|a| *a))),
+        "ACCOUNT" => {
+            let account = match params.first() {
+                Some(a) if *a != "*" => Some(*a),
+                Some(_) => None,
+                None => return None,
+            };
+            Some(::irc::IRCMessageType::ACCOUNT(account))
+        },
+        "CHGHOST" => {
+            if params.len() < 2 {
+                return None;
+            }
+            Some(::irc::IRCMessageType::CHGHOST((params[0], params[1])))
+        },
+        "BATCH" => {
+            let reference = match params.first() {
+                Some(r) => r,
+                None => return None,
+            };
+            let kind = params.get(1).map(|k| *k).unwrap_or("");
+            Some(::irc::IRCMessageType::BATCH((reference, kind)))
+        },
         _ => {
-            match message_type.parse::<u64>() {
-                Ok(i) => Some(::irc::IRCMessageType::INFO(i)),
-                Err(_) => None,
+            let code = match command.len() {
+                3 => command.parse::<u16>().ok(),
+                _ => None,
+            };
+            match code {
+                Some(c) => Some(::irc::IRCMessageType::NUMERIC(::numeric::parse(c, params))),
+                None => Some(::irc::IRCMessageType::UNKNOWN((command, params.to_vec()))),
             }
         },
     }
 }
+
+fn uppercase<'a>(text: &'a str) -> Cow<'a, str> {
+    match text.bytes().any(|b| b.is_ascii_lowercase()) {
+        true => Cow::Owned(text.to_ascii_uppercase()),
+        false => Cow::Borrowed(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use irc::{Client, Entity, IRCMessage, IRCMessageType, IRCServerMessage};
+    use numeric::Numeric;
+    use super::{escape_tag_value, format_tags, parse_from, parse_line, trim_line,
+                unescape_tag_value};
+
+    fn server_message(line: &str) -> IRCServerMessage {
+        match parse_line(line.as_bytes(), line) {
+            Some(IRCMessage::IRCServerMessage(m)) => m,
+            other => panic!("{:?} parsed as {:?}", line, other),
+        }
+    }
+
+    fn client(nick: &str, username: &str, address: &str) -> Entity<'static> {
+        Entity::Client(Client {
+            nick: nick.to_string().into(),
+            username: username.to_string().into(),
+            address: address.to_string().into(),
+        })
+    }
+
+    fn assert_from(entity: &Option<Entity>, expected: Entity) {
+        match (entity, &expected) {
+            (&Some(Entity::Client(ref a)), &Entity::Client(ref b)) => assert_eq!(a, b),
+            (&Some(Entity::Server(ref a)), &Entity::Server(ref b)) => assert_eq!(a, b),
+            _ => panic!("expected {:?}, got {:?}", expected, entity),
+        }
+    }
+
+    #[test]
+    fn prefix_command_and_params() {
+        let message = server_message(":nick!user@host.example PRIVMSG #chan :hello world\r\n");
+        assert_from(&message.from, client("nick", "user", "host.example"));
+        assert_eq!(message.line.command, "PRIVMSG");
+        assert_eq!(message.line.params(), &["#chan", "hello world"]);
+        assert_eq!(message.target, "#chan");
+        match message.message {
+            IRCMessageType::PRIVMSG(text) => assert_eq!(text, "hello world"),
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ipv6_hosts_in_prefixes() {
+        let message = server_message(":nick!~user@2001:db8::1 QUIT :bye");
+        assert_from(&message.from, client("nick", "~user", "2001:db8::1"));
+        let message = server_message(":nick!user@::1 JOIN #chan");
+        assert_from(&message.from, client("nick", "user", "::1"));
+        assert_from(&parse_from("nick@fe80::1%eth0"), client("nick", "", "fe80::1%eth0"));
+    }
+
+    #[test]
+    fn server_and_nick_only_prefixes() {
+        let message = server_message(":irc.example.net 001 korasho :Welcome korasho!u@h");
+        assert_from(&message.from, Entity::Server("irc.example.net".into()));
+        match message.message {
+            IRCMessageType::NUMERIC(Numeric::Welcome((ref nick, ref text))) => {
+                assert_eq!(nick, "korasho");
+                assert_eq!(text, "Welcome korasho!u@h");
+            },
+            ref other => panic!("unexpected {:?}", other),
+        }
+        let message = server_message(":korasho MODE korasho :+i");
+        assert_from(&message.from, client("korasho", "", ""));
+        assert!(server_message("NOTICE * :no prefix").from.is_none());
+    }
+
+    #[test]
+    fn middle_params_and_spacing() {
+        let message = server_message("MODE  #chan  +o   nick");
+        assert_eq!(message.line.params(), &["#chan", "+o", "nick"]);
+        assert!(!message.line.trailing);
+        let message = server_message("PRIVMSG #chan :");
+        assert_eq!(message.line.params(), &["#chan", ""]);
+        assert!(message.line.trailing);
+        let message = server_message("PRIVMSG #chan ::-) a:b");
+        assert_eq!(message.line.params(), &["#chan", ":-) a:b"]);
+    }
+
+    #[test]
+    fn at_most_fifteen_params() {
+        let message = server_message("FOO 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16");
+        assert_eq!(message.line.params().len(), 15);
+        assert_eq!(message.line.params()[14], "15 16");
+        let message = server_message("FOO 1 2 3 4 5 6 7 8 9 10 11 12 13 14 :15 16");
+        assert_eq!(message.line.params()[14], "15 16");
+    }
+
+    #[test]
+    fn commands_are_case_insensitive() {
+        match server_message("privmsg #chan :hi").message {
+            IRCMessageType::PRIVMSG(_) => {},
+            ref other => panic!("unexpected {:?}", other),
+        }
+        match server_message("FOO a :b c").message {
+            IRCMessageType::UNKNOWN((ref command, ref params)) => {
+                assert_eq!(command, "FOO");
+                assert_eq!(params, &["a", "b c"]);
+            },
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batches() {
+        match server_message("BATCH +yXNAbvnRHTRBv chathistory #chan").message {
+            IRCMessageType::BATCH((reference, kind)) => {
+                assert_eq!(reference, "+yXNAbvnRHTRBv");
+                assert_eq!(kind, "chathistory");
+            },
+            ref other => panic!("unexpected {:?}", other),
+        }
+        let message = server_message("@batch=yXNAbvnRHTRBv :n!u@h PRIVMSG #chan :!help");
+        assert_eq!(message.line.tag("batch").as_ref().map(|b| b.as_ref()), Some("yXNAbvnRHTRBv"));
+        assert!(parse_line(b"BATCH", "BATCH").is_none());
+    }
+
+    #[test]
+    fn pings() {
+        for line in ["PING :irc.example.net", "PING irc.example.net"].iter() {
+            match parse_line(line.as_bytes(), line) {
+                Some(IRCMessage::IRCPing(ref ping)) => assert_eq!(ping.token, "irc.example.net"),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let lines = ["", " ", ":prefix-only", ": PRIVMSG #c :x", "@tags-only", "@a=b :n!u@h"];
+        for line in lines.iter() {
+            assert!(parse_line(line.as_bytes(), line).is_none(), "{:?}", line);
+        }
+        assert!(parse_line(b"KICK #chan", "KICK #chan").is_none());
+    }
+
+    #[test]
+    fn trims_line_endings() {
+        assert_eq!(trim_line(b"PING x\r\n"), b"PING x");
+        assert_eq!(trim_line(b"PING x\n"), b"PING x");
+        assert_eq!(trim_line(b"\r\n"), b"");
+    }
+
+    #[test]
+    fn tag_values_escape_and_unescape() {
+        let cases = [
+            ("plain", "plain"),
+            ("a;b", "a\\:b"),
+            ("two words", "two\\swords"),
+            ("back\\slash", "back\\\\slash"),
+            ("line\r\nbreak", "line\\r\\nbreak"),
+            ("", ""),
+        ];
+        for &(value, escaped) in cases.iter() {
+            assert_eq!(escape_tag_value(value), escaped);
+            assert_eq!(unescape_tag_value(escaped), value);
+        }
+    }
+
+    #[test]
+    fn lenient_unescaping() {
+        assert_eq!(unescape_tag_value("trailing\\"), "trailing");
+        assert_eq!(unescape_tag_value("\\b\\x"), "bx");
+        assert_eq!(unescape_tag_value("caf\u{e9}\\s!"), "caf\u{e9} !");
+    }
+
+    #[test]
+    fn tags_are_formatted_and_read_back() {
+        assert_eq!(format_tags(&BTreeMap::new()), "");
+        let mut tags = BTreeMap::new();
+        tags.insert("+draft/reply".to_string(), "abc".to_string());
+        tags.insert("+example.com/flag".to_string(), "".to_string());
+        tags.insert("note".to_string(), "a; b\\c".to_string());
+        let formatted = format_tags(&tags);
+        assert_eq!(formatted, "@+draft/reply=abc;+example.com/flag;note=a\\:\\sb\\\\c ");
+        let line = format!("{tags}PRIVMSG #chan :hi", tags=formatted);
+        let message = server_message(&line);
+        let parsed: BTreeMap<String, String> = message.line.tags()
+            .map(|(key, value)| (key.to_string(), value.into_owned()))
+            .collect();
+        assert_eq!(parsed, tags);
+    }
+
+    #[test]
+    fn empty_and_valueless_tags() {
+        let message = server_message("@a=;b;;c=1 PING2 x");
+        let tags: Vec<(&str, String)> = message.line.tags()
+            .map(|(key, value)| (key, value.into_owned()))
+            .collect();
+        assert_eq!(tags, vec![("a", "".to_string()), ("b", "".to_string()),
+                              ("c", "1".to_string())]);
+        assert_eq!(message.line.tag("b").as_ref().map(|v| v.as_ref()), Some(""));
+        assert!(message.line.tag("d").is_none());
+    }
+}
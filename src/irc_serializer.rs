@@ -0,0 +1,239 @@
+use irc::{Entity, IRCTags};
+use message::Message;
+
+pub fn serialize(message: &Message) -> Result<String, String> {
+    let tags: IRCTags = message.tags().map(|(k, v)| (k.to_string(), v.into_owned())).collect();
+    write_line(&tags, message.prefix, message.command, message.params(), message.trailing)
+}
+
+pub fn serialize_line<S: AsRef<str>>(tags: &IRCTags, from: Option<&Entity>, command: &str,
+                                     params: &[S], trailing: bool) -> Result<String, String> {
+    let from = from.map(format_from);
+    write_line(tags, from.as_ref().map(|f| f.as_str()), command, params, trailing)
+}
+
+fn write_line<S: AsRef<str>>(tags: &IRCTags, from: Option<&str>, command: &str,
+                             params: &[S], trailing: bool) -> Result<String, String> {
+    for (key, value) in tags.iter() {
+        if !valid_tag_key(key) || value.contains('\0') {
+            return Err(format!("Invalid tag: {key:?}={value:?}", key=key, value=value));
+        }
+    }
+    let mut line = ::irc_parser::format_tags(tags);
+    if let Some(from) = from {
+        if from.len() < 1 || from.contains(' ') || has_line_break(&from) {
+            return Err(format!("Invalid prefix: {from:?}", from=from));
+        }
+        line.push(':');
+        line.push_str(from);
+        line.push(' ');
+    }
+    if command.len() < 1 || !command.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return Err(format!("Invalid command: {command:?}", command=command));
+    }
+    line.push_str(command);
+    let count = params.len();
+    for (i, param) in params.iter().enumerate() {
+        let param = param.as_ref();
+        if has_line_break(param) {
+            return Err(format!("Parameter {i} of {command} contains CR, LF or NUL",
+                               i=i,
+                               command=command));
+        }
+        line.push(' ');
+        if i + 1 == count && (trailing || needs_trailing(param)) {
+            line.push(':');
+        } else if needs_trailing(param) {
+            return Err(format!("Parameter {i} of {command} must be the last one: {param:?}",
+                               i=i,
+                               command=command,
+                               param=param));
+        }
+        line.push_str(param);
+    }
+    line.push_str("\r\n");
+    Ok(line)
+}
+
+pub fn sanitize(text: &str) -> String {
+    text.chars().map(|c| match c {
+        '\r' | '\n' | '\0' => ' ',
+        _ => c,
+    }).collect()
+}
+
+fn needs_trailing(param: &str) -> bool {
+    param.len() < 1 || param.starts_with(':') || param.contains(' ')
+}
+
+fn valid_tag_key(key: &str) -> bool {
+    key.len() > 0 && key.bytes().all(|b| b.is_ascii_alphanumeric() || b"+-/.".contains(&b))
+}
+
+fn has_line_break(text: &str) -> bool {
+    text.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0)
+}
+
+fn format_from(from: &Entity) -> String {
+    match *from {
+        Entity::Server(ref s) => s.to_string(),
+        Entity::Client(ref c) => {
+            let mut mask = c.nick.to_string();
+            if c.username.len() > 0 {
+                mask.push('!');
+                mask.push_str(&c.username);
+            }
+            if c.address.len() > 0 {
+                mask.push('@');
+                mask.push_str(&c.address);
+            }
+            mask
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use irc::{Client, Entity, IRCTags};
+    use test_util::Rng;
+    use super::{sanitize, serialize, serialize_line};
+
+    const MIDDLE_CHARS: &'static [u8] = b"abcXYZ019#&!@:.-[]{}|^";
+    const TRAILING_CHARS: &'static [u8] = b"ab Z9 :;=\\#\x02\x03";
+    const TAG_CHARS: &'static [u8] = b"ab9 ;=\\:\r\n";
+
+    fn middle(rng: &mut Rng) -> String {
+        let mut param = rng.string(MIDDLE_CHARS, 1, 8);
+        while param.starts_with(':') {
+            param.remove(0);
+        }
+        if param.len() < 1 {
+            param.push('x');
+        }
+        param
+    }
+
+    #[test]
+    fn generated_messages_round_trip() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for _ in 0..2000 {
+            let mut tags: IRCTags = BTreeMap::new();
+            for _ in 0..rng.below(4) {
+                let key = format!("{plus}k{n}", plus=["", "+"][rng.below(2)], n=rng.below(100));
+                tags.insert(key, rng.string(TAG_CHARS, 0, 6));
+            }
+            let from = match rng.below(3) {
+                0 => None,
+                1 => Some(Entity::Server("irc.example.net".into())),
+                _ => Some(Entity::Client(Client {
+                    nick: middle(&mut rng).replace(|c| c == '!' || c == '@', "n").into(),
+                    username: "user".into(),
+                    address: ["host.example", "2001:db8::1"][rng.below(2)].into(),
+                })),
+            };
+            let mut params: Vec<String> = (0..rng.below(14)).map(|_| middle(&mut rng)).collect();
+            if rng.below(2) == 0 {
+                params.push(rng.string(TRAILING_CHARS, 0, 12));
+            }
+            let trailing = rng.below(4) == 0 && params.len() > 0;
+            let line = serialize_line(&tags, from.as_ref(), "PRIVMSG", &params, trailing).unwrap();
+            assert!(line.ends_with("\r\n"), "{:?}", line);
+            assert_eq!(line.matches('\n').count(), 1, "{:?}", line);
+            let message = ::message::parse(&line).unwrap();
+            let parsed_tags: IRCTags = message.tags()
+                .map(|(k, v)| (k.to_string(), v.into_owned()))
+                .collect();
+            assert_eq!(parsed_tags, tags, "{:?}", line);
+            let prefix = from.as_ref().map(super::format_from);
+            assert_eq!(message.prefix, prefix.as_ref().map(|p| p.as_str()), "{:?}", line);
+            assert_eq!(message.command, "PRIVMSG");
+            assert_eq!(message.params(), &params.iter().map(|p| p.as_str()).collect::<Vec<_>>()[..],
+                       "{:?}", line);
+            if trailing {
+                assert!(message.trailing, "{:?}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn parsed_messages_round_trip() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for _ in 0..2000 {
+            let mut line = String::new();
+            if rng.below(2) == 0 {
+                line.push('@');
+                for i in 0..1 + rng.below(3) {
+                    let key = format!("{plus}k{n}", plus=["", "+"][rng.below(2)], n=rng.below(9));
+                    let value = rng.string(b"ab\\:s;r", 0, 5).replace(';', "");
+                    let separator = if i > 0 { ";" } else { "" };
+                    line.push_str(&format!("{separator}{key}={value}",
+                                           separator=separator, key=key, value=value));
+                }
+                line.push(' ');
+            }
+            if rng.below(2) == 0 {
+                line.push_str(&format!(":{nick}!u@2001:db8::1 ", nick=middle(&mut rng)));
+            }
+            line.push_str(["PRIVMSG", "privmsg", "005", "TAGMSG"][rng.below(4)]);
+            for _ in 0..rng.below(16) {
+                line.push_str(["  ", " "][rng.below(2)]);
+                line.push_str(&middle(&mut rng));
+            }
+            if rng.below(2) == 0 {
+                line.push_str(" :");
+                line.push_str(&rng.string(TRAILING_CHARS, 0, 12));
+            }
+            let message = ::message::parse(&line).unwrap();
+            let serialized = serialize(&message).unwrap();
+            assert_eq!(::message::parse(&serialized), Some(message),
+                       "{:?} {:?}", line, serialized);
+        }
+    }
+
+    #[test]
+    fn trailing_marker_only_when_needed() {
+        let tags = BTreeMap::new();
+        assert_eq!(serialize_line(&tags, None, "JOIN", &["#chan"], false).unwrap(), "JOIN #chan\r\n");
+        assert_eq!(serialize_line(&tags, None, "PRIVMSG", &["#c", "hi there"], false).unwrap(),
+                   "PRIVMSG #c :hi there\r\n");
+        assert_eq!(serialize_line(&tags, None, "PRIVMSG", &["#c", ""], false).unwrap(),
+                   "PRIVMSG #c :\r\n");
+        assert_eq!(serialize_line(&tags, None, "PRIVMSG", &["#c", ":)"], false).unwrap(),
+                   "PRIVMSG #c ::)\r\n");
+        assert_eq!(serialize_line(&tags, None, "QUIT", &["bye"], true).unwrap(), "QUIT :bye\r\n");
+        assert!(serialize_line(&tags, None, "PRIVMSG", &["a b", "c"], false).is_err());
+        assert!(serialize_line(&tags, None, "PRIVMSG", &[":a", "c"], false).is_err());
+        assert!(serialize_line(&tags, None, "PRIV MSG", &["a"], false).is_err());
+    }
+
+    #[test]
+    fn line_breaks_and_nul_are_rejected() {
+        let tags = BTreeMap::new();
+        for bad in ["a\rb", "a\nQUIT :x", "a\0b"].iter() {
+            assert!(serialize_line(&tags, None, "PRIVMSG", &["#c", bad], false).is_err());
+            assert!(serialize_line(&tags, None, "PRIVMSG", &[*bad, "x"], false).is_err());
+        }
+        let server = Entity::Server("evil\r\nQUIT".into());
+        assert!(serialize_line(&tags, Some(&server), "PING", &["x"], false).is_err());
+        let mut tags = BTreeMap::new();
+        tags.insert("label".to_string(), "a\0b".to_string());
+        assert!(serialize_line(&tags, None, "PING", &["x"], false).is_err());
+        let mut tags = BTreeMap::new();
+        tags.insert("bad key".to_string(), "v".to_string());
+        assert!(serialize_line(&tags, None, "PING", &["x"], false).is_err());
+    }
+
+    #[test]
+    fn tag_values_with_line_breaks_are_escaped() {
+        let mut tags = BTreeMap::new();
+        tags.insert("+draft/reply".to_string(), "a;b c\\d\r\n".to_string());
+        let line = serialize_line(&tags, None, "TAGMSG", &["#c"], false).unwrap();
+        assert_eq!(line, "@+draft/reply=a\\:b\\sc\\\\d\\r\\n TAGMSG #c\r\n");
+    }
+
+    #[test]
+    fn sanitize_replaces_line_breaks() {
+        assert_eq!(sanitize("a\r\nPRIVMSG #x :b\0c"), "a  PRIVMSG #x :b c");
+    }
+}
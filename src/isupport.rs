@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use casemap::CaseMapping;
+
+const DEFAULT_PREFIX: [(char, char); 2] = [('o', '@'), ('v', '+')];
+const DEFAULT_CHANMODES: &'static str = "beI,k,l,imnpst";
+const DEFAULT_CHANTYPES: &'static str = "#&";
+const DEFAULT_NICKLEN: usize = 9;
+const DEFAULT_CHANNELLEN: usize = 200;
+const DEFAULT_HOSTLEN: usize = 63;
+const DEFAULT_MODES: usize = 3;
+
+#[derive(Debug)]
+pub struct ISupport {
+    pub casemapping: CaseMapping,
+    pub chantypes: String,
+    pub statusmsg: String,
+    pub prefixes: Vec<(char, char)>,
+    pub list_modes: String,
+    pub always_param_modes: String,
+    pub set_param_modes: String,
+    pub flag_modes: String,
+    pub nicklen: usize,
+    pub channellen: usize,
+    pub hostlen: usize,
+    pub topiclen: Option<usize>,
+    pub modes: Option<usize>,
+    pub monitor: Option<usize>,
+    pub network: Option<String>,
+    pub tokens: BTreeMap<String, String>,
+}
+
+impl Default for ISupport {
+    fn default() -> ISupport {
+        let mut isupport = ISupport {
+            casemapping: CaseMapping::Rfc1459,
+            chantypes: DEFAULT_CHANTYPES.to_string(),
+            statusmsg: "".to_string(),
+            prefixes: DEFAULT_PREFIX.to_vec(),
+            list_modes: "".to_string(),
+            always_param_modes: "".to_string(),
+            set_param_modes: "".to_string(),
+            flag_modes: "".to_string(),
+            nicklen: DEFAULT_NICKLEN,
+            channellen: DEFAULT_CHANNELLEN,
+            hostlen: DEFAULT_HOSTLEN,
+            topiclen: None,
+            modes: Some(DEFAULT_MODES),
+            monitor: None,
+            network: None,
+            tokens: BTreeMap::new(),
+        };
+        isupport.set_chanmodes(DEFAULT_CHANMODES);
+        isupport
+    }
+}
+
+impl ISupport {
+    pub fn update(&mut self, tokens: &[String]) {
+        for param in tokens {
+            if param.starts_with('-') {
+                self.reset(param.trim_start_matches('-'));
+                continue;
+            }
+            let mut token_iter = param.splitn(2, '=');
+            let name = token_iter.next().unwrap_or("").to_string();
+            let value = unescape_value(token_iter.next().unwrap_or(""));
+            self.set(&name, &value);
+            self.tokens.insert(name, value);
+        }
+    }
+
+    fn reset(&mut self, name: &str) {
+        let defaults = ISupport::default();
+        match name {
+            "CASEMAPPING" => self.casemapping = defaults.casemapping,
+            "CHANTYPES" => self.chantypes = defaults.chantypes,
+            "STATUSMSG" => self.statusmsg = defaults.statusmsg,
+            "PREFIX" => self.prefixes = defaults.prefixes,
+            "CHANMODES" => self.set_chanmodes(DEFAULT_CHANMODES),
+            "NICKLEN" => self.nicklen = defaults.nicklen,
+            "CHANNELLEN" => self.channellen = defaults.channellen,
+            "HOSTLEN" => self.hostlen = defaults.hostlen,
+            "TOPICLEN" => self.topiclen = defaults.topiclen,
+            "MODES" => self.modes = defaults.modes,
+            "MONITOR" => self.monitor = defaults.monitor,
+            "NETWORK" => self.network = defaults.network,
+            _ => {},
+        }
+        self.tokens.remove(name);
+    }
+
+    fn set(&mut self, name: &str, value: &str) {
+        match name {
+            "CASEMAPPING" => self.casemapping = CaseMapping::from_token(value),
+            "CHANTYPES" => self.chantypes = value.to_string(),
+            "STATUSMSG" => self.statusmsg = value.to_string(),
+            "PREFIX" => self.set_prefix(value),
+            "CHANMODES" => self.set_chanmodes(value),
+            "NICKLEN" => self.nicklen = value.parse().unwrap_or(DEFAULT_NICKLEN),
+            "CHANNELLEN" => self.channellen = value.parse().unwrap_or(DEFAULT_CHANNELLEN),
+            "HOSTLEN" => self.hostlen = value.parse().unwrap_or(DEFAULT_HOSTLEN),
+            "TOPICLEN" => self.topiclen = value.parse().ok(),
+            "MODES" => self.modes = value.parse().ok(),
+            "MONITOR" => self.monitor = Some(value.parse().unwrap_or(0)),
+            "NETWORK" => self.network = Some(value.to_string()),
+            _ => {},
+        }
+    }
+
+    fn set_prefix(&mut self, value: &str) {
+        let value = value.trim_start_matches('(');
+        let mut prefix_iter = value.splitn(2, ')');
+        let modes = prefix_iter.next().unwrap_or("");
+        let symbols = prefix_iter.next().unwrap_or("");
+        self.prefixes = modes.chars().zip(symbols.chars()).collect();
+    }
+
+    fn set_chanmodes(&mut self, value: &str) {
+        let mut groups = value.split(',');
+        self.list_modes = groups.next().unwrap_or("").to_string();
+        self.always_param_modes = groups.next().unwrap_or("").to_string();
+        self.set_param_modes = groups.next().unwrap_or("").to_string();
+        self.flag_modes = groups.next().unwrap_or("").to_string();
+    }
+
+    pub fn is_channel(&self, name: &str) -> bool {
+        match name.chars().next() {
+            Some(c) => self.chantypes.contains(c),
+            None => false,
+        }
+    }
+
+    /// Drops the STATUSMSG prefixes from targets like `@#chan`, which only reach
+    /// the ops of `#chan` but are still in the channel.
+    pub fn strip_statusmsg<'a>(&self, target: &'a str) -> &'a str {
+        target.trim_start_matches(|c| self.statusmsg.contains(c))
+    }
+
+    pub fn prefix_mode(&self, symbol: char) -> Option<char> {
+        self.prefixes.iter().find(|&&(_, s)| s == symbol).map(|&(m, _)| m)
+    }
+
+    pub fn prefix_symbol(&self, mode: char) -> Option<char> {
+        self.prefixes.iter().find(|&&(m, _)| m == mode).map(|&(_, s)| s)
+    }
+
+    pub fn prefix_rank(&self, mode: char) -> usize {
+        self.prefixes.iter().position(|&(m, _)| m == mode).unwrap_or(self.prefixes.len())
+    }
+
+    pub fn mode_takes_param(&self, mode: char, adding: bool) -> bool {
+        if self.prefix_symbol(mode).is_some() {
+            return true;
+        }
+        if self.list_modes.contains(mode) || self.always_param_modes.contains(mode) {
+            return true;
+        }
+        adding && self.set_param_modes.contains(mode)
+    }
+}
+
+fn unescape_value(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while rest.len() > 0 {
+        let escaped = match rest.len() >= 4 && rest.starts_with(b"\\x") {
+            true => ::std::str::from_utf8(&rest[2..4]).ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            false => None,
+        };
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &rest[4..];
+            },
+            None => {
+                bytes.push(rest[0]);
+                rest = &rest[1..];
+            },
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use casemap::CaseMapping;
+    use super::ISupport;
+
+    fn isupport(tokens: &[&str]) -> ISupport {
+        let mut isupport = ISupport::default();
+        isupport.update(&tokens.iter().map(|t| t.to_string()).collect::<Vec<String>>());
+        isupport
+    }
+
+    #[test]
+    fn defaults_before_005() {
+        let isupport = ISupport::default();
+        assert_eq!(isupport.casemapping, CaseMapping::Rfc1459);
+        assert!(isupport.is_channel("#chan") && isupport.is_channel("&local"));
+        assert!(!isupport.is_channel("nick") && !isupport.is_channel(""));
+        assert_eq!(isupport.prefix_symbol('o'), Some('@'));
+        assert_eq!(isupport.list_modes, "beI");
+        assert_eq!(isupport.modes, Some(3));
+        assert_eq!(isupport.strip_statusmsg("@#chan"), "@#chan");
+    }
+
+    #[test]
+    fn statusmsg_prefixes() {
+        let isupport = isupport(&["STATUSMSG=@+"]);
+        assert_eq!(isupport.strip_statusmsg("@#chan"), "#chan");
+        assert_eq!(isupport.strip_statusmsg("+#chan"), "#chan");
+        assert_eq!(isupport.strip_statusmsg("@+#chan"), "#chan");
+        assert_eq!(isupport.strip_statusmsg("nick"), "nick");
+        assert!(isupport.is_channel(isupport.strip_statusmsg("@#chan")));
+    }
+
+    #[test]
+    fn common_tokens() {
+        let isupport = isupport(&["CASEMAPPING=ascii", "CHANTYPES=#", "PREFIX=(qaohv)~&@%+",
+                                  "CHANMODES=beI,k,l,imnpst", "NICKLEN=30", "TOPICLEN=390",
+                                  "MODES", "MONITOR=100", "NETWORK=Example\\x20Net", "WHOX"]);
+        assert_eq!(isupport.casemapping, CaseMapping::Ascii);
+        assert!(!isupport.is_channel("&local"));
+        assert_eq!(isupport.prefix_mode('%'), Some('h'));
+        assert_eq!(isupport.prefix_rank('q'), 0);
+        assert_eq!(isupport.prefix_rank('v'), 4);
+        assert_eq!(isupport.prefix_rank('x'), 5);
+        assert_eq!(isupport.nicklen, 30);
+        assert_eq!(isupport.topiclen, Some(390));
+        assert_eq!(isupport.modes, None);
+        assert_eq!(isupport.monitor, Some(100));
+        assert_eq!(isupport.network, Some("Example Net".to_string()));
+        assert_eq!(isupport.tokens.get("WHOX"), Some(&"".to_string()));
+    }
+
+    #[test]
+    fn mode_parameters() {
+        let isupport = isupport(&["PREFIX=(ov)@+", "CHANMODES=beI,k,lf,imnpst"]);
+        assert!(isupport.mode_takes_param('o', false));
+        assert!(isupport.mode_takes_param('b', false));
+        assert!(isupport.mode_takes_param('k', false));
+        assert!(isupport.mode_takes_param('l', true));
+        assert!(!isupport.mode_takes_param('l', false));
+        assert!(!isupport.mode_takes_param('m', true));
+    }
+
+    #[test]
+    fn negated_tokens_restore_defaults() {
+        let mut isupport = isupport(&["CASEMAPPING=ascii", "NICKLEN=30", "EXCEPTS"]);
+        isupport.update(&["-CASEMAPPING".to_string(), "-NICKLEN".to_string(),
+                          "-EXCEPTS".to_string()]);
+        assert_eq!(isupport.casemapping, CaseMapping::Rfc1459);
+        assert_eq!(isupport.nicklen, 9);
+        assert!(!isupport.tokens.contains_key("EXCEPTS"));
+    }
+
+    #[test]
+    fn malformed_values_fall_back() {
+        let isupport = isupport(&["NICKLEN=lots", "PREFIX=(ov", "NETWORK=bad\\xZZescape"]);
+        assert_eq!(isupport.nicklen, 9);
+        assert!(isupport.prefixes.is_empty());
+        assert_eq!(isupport.network, Some("bad\\xZZescape".to_string()));
+    }
+}
@@ -3,14 +3,37 @@ extern crate time;
 extern crate toml;
 
 use std::env;
+use std::io;
 use std::thread;
 use std::env::Args;
 
 mod bot;
+mod capabilities;
+mod casemap;
+mod channel;
 mod config;
 mod connection;
+mod ctcp;
+mod encoding;
+mod event;
+mod formatting;
 mod irc;
 mod irc_parser;
+mod irc_serializer;
+mod isupport;
+mod message;
+mod numeric;
+mod password;
+mod permissions;
+mod plugin;
+mod plugins;
+mod sasl;
+mod session;
+mod send_queue;
+mod split;
+mod supervisor;
+#[cfg(test)]
+mod test_util;
 
 
 fn read_file_name(args: &mut Args) -> String {
@@ -20,7 +43,21 @@ fn read_file_name(args: &mut Args) -> String {
     panic!("Need a config file path!");
 }
 
+fn hash_password() {
+    println!("Password:");
+    let mut password = String::new();
+    if let Err(err) = io::stdin().read_line(&mut password) {
+        panic!("Unable to read a password: {}", err);
+    }
+    let password = password.trim_end_matches(|c| c == '\r' || c == '\n');
+    println!("admin_password_hash = \"{hash}\"", hash=password::hash(password));
+}
+
 fn main() {
+    if env::args().nth(1).as_ref().map(|a| a.as_str()) == Some("--hash-password") {
+        hash_password();
+        return;
+    }
     println!("Starting bot");
 
     let filename = read_file_name(&mut env::args());
@@ -33,16 +70,7 @@ fn main() {
     let handles: Vec<_> = config.servers.clone().into_iter().map(|server| {
         let captured_config = config.clone();
         thread::spawn(move || {
-            let connection = match connection::connect(server.clone()) {
-                Ok(s) => s,
-                Err(err) => {
-                    println!("Could not connect to {server}: {err}", server=server, err=err);
-                    return;
-                },
-            };
-            let bot = bot::new(&captured_config, server.clone());
-            let mut irc = irc::new(connection, &captured_config, bot);
-            irc.run();
+            supervisor::run(&captured_config, server);
         })
     }).collect();
 
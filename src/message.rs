@@ -0,0 +1,211 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+const MAX_PARAMS: usize = 15;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+pub struct Message<'a> {
+    pub raw: &'a str,
+    pub tags: &'a str,
+    pub prefix: Option<&'a str>,
+    pub command: &'a str,
+    pub trailing: bool,
+    params: [&'a str; MAX_PARAMS],
+    param_count: usize,
+}
+
+/// Messages are equal when they carry the same tags, prefix, command and
+/// params; how the line spelled them (escapes, spacing, tag order) is ignored.
+impl<'a, 'b> PartialEq<Message<'b>> for Message<'a> {
+    fn eq(&self, other: &Message<'b>) -> bool {
+        let tags: BTreeMap<&str, Cow<str>> = self.tags().collect();
+        let other_tags: BTreeMap<&str, Cow<str>> = other.tags().collect();
+        tags == other_tags && self.prefix == other.prefix && self.command == other.command &&
+            self.params() == other.params() && self.trailing == other.trailing
+    }
+}
+
+pub struct Tags<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = (&'a str, Cow<'a, str>);
+
+    fn next(&mut self) -> Option<(&'a str, Cow<'a, str>)> {
+        while self.rest.len() > 0 {
+            let end = self.rest.find(';').unwrap_or(self.rest.len());
+            let tag = &self.rest[0..end];
+            self.rest = match end < self.rest.len() {
+                true => &self.rest[end + 1..],
+                false => "",
+            };
+            if tag.len() < 1 {
+                continue;
+            }
+            let mut tag_iter = tag.splitn(2, '=');
+            let key = tag_iter.next().unwrap_or("");
+            let value = match tag_iter.next() {
+                Some(v) if v.contains('\\') => Cow::Owned(::irc_parser::unescape_tag_value(v)),
+                Some(v) => Cow::Borrowed(v),
+                None => Cow::Borrowed(""),
+            };
+            return Some((key, value));
+        }
+        None
+    }
+}
+
+impl<'a> Message<'a> {
+    pub fn params(&self) -> &[&'a str] {
+        &self.params[0..self.param_count]
+    }
+
+    pub fn param(&self, index: usize) -> Option<&'a str> {
+        self.params().get(index).map(|p| *p)
+    }
+
+    pub fn is_command(&self, command: &str) -> bool {
+        self.command.eq_ignore_ascii_case(command)
+    }
+
+    pub fn tags(&self) -> Tags<'a> {
+        Tags { rest: self.tags }
+    }
+
+    pub fn tag(&self, key: &str) -> Option<Cow<'a, str>> {
+        self.tags().find(|&(k, _)| k == key).map(|(_, v)| v)
+    }
+}
+
+pub fn parse<'a>(line: &'a str) -> Option<Message<'a>> {
+    let raw = line.trim_end_matches(|c| c == '\r' || c == '\n');
+    let mut rest = raw;
+    let mut tags = "";
+    if rest.starts_with('@') {
+        let end = match rest.find(' ') {
+            Some(e) => e,
+            None => return None,
+        };
+        tags = &rest[1..end];
+        rest = rest[end..].trim_start_matches(' ');
+    }
+    let mut prefix = None;
+    if rest.starts_with(':') {
+        let end = match rest.find(' ') {
+            Some(e) => e,
+            None => return None,
+        };
+        if end < 2 {
+            return None;
+        }
+        prefix = Some(&rest[1..end]);
+        rest = rest[end..].trim_start_matches(' ');
+    }
+    let end = rest.find(' ').unwrap_or(rest.len());
+    if end == 0 {
+        return None;
+    }
+    let command = &rest[0..end];
+    rest = rest[end..].trim_start_matches(' ');
+    let mut params = [""; MAX_PARAMS];
+    let mut param_count = 0;
+    let mut trailing = false;
+    while rest.len() > 0 {
+        if rest.starts_with(':') {
+            params[param_count] = &rest[1..];
+            param_count += 1;
+            trailing = true;
+            break;
+        }
+        if param_count == MAX_PARAMS - 1 {
+            params[param_count] = rest;
+            param_count += 1;
+            trailing = true;
+            break;
+        }
+        let end = rest.find(' ').unwrap_or(rest.len());
+        params[param_count] = &rest[0..end];
+        param_count += 1;
+        rest = rest[end..].trim_start_matches(' ');
+    }
+    Some(Message {
+        raw: raw,
+        tags: tags,
+        prefix: prefix,
+        command: command,
+        trailing: trailing,
+        params: params,
+        param_count: param_count,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use super::parse;
+
+    fn within(line: &str, field: &str) -> bool {
+        let start = line.as_ptr() as usize;
+        let field = field.as_ptr() as usize;
+        field >= start && field <= start + line.len()
+    }
+
+    #[test]
+    fn fields_borrow_from_the_line() {
+        let line = "@time=2020-01-01T00:00:00Z;+x :nick!user@host PRIVMSG #chan :hello there\r\n";
+        let message = parse(line).unwrap();
+        assert_eq!(message.raw, &line[..line.len() - 2]);
+        assert_eq!(message.prefix, Some("nick!user@host"));
+        assert_eq!(message.command, "PRIVMSG");
+        assert_eq!(message.params(), &["#chan", "hello there"]);
+        assert!(message.trailing);
+        assert!(within(line, message.raw) && within(line, message.tags) &&
+                within(line, message.command));
+        assert!(message.params().iter().all(|p| within(line, p)));
+        match message.tag("time") {
+            Some(Cow::Borrowed(time)) => assert!(within(line, time)),
+            other => panic!("expected a borrowed tag, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tags_are_only_copied_when_escaped() {
+        let message = parse("@a=b\\sc;d;e= PING x").unwrap();
+        let tags: Vec<(&str, Cow<str>)> = message.tags().collect();
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[0], ("a", Cow::Owned::<str>("b c".to_string())));
+        assert_eq!(tags[1], ("d", Cow::Borrowed("")));
+        assert_eq!(tags[2], ("e", Cow::Borrowed("")));
+        assert_eq!(message.tag("missing"), None);
+    }
+
+    #[test]
+    fn params_and_accessors() {
+        let message = parse("MODE #chan +ov a b").unwrap();
+        assert_eq!(message.param(0), Some("#chan"));
+        assert_eq!(message.param(3), Some("b"));
+        assert_eq!(message.param(4), None);
+        assert!(!message.trailing);
+        assert!(message.is_command("mode"));
+    }
+
+    #[test]
+    fn fifteenth_param_takes_the_rest() {
+        let message = parse("005 n 1 2 3 4 5 6 7 8 9 10 11 12 13 rest of  line").unwrap();
+        assert_eq!(message.params().len(), 15);
+        assert_eq!(message.param(14), Some("rest of  line"));
+        assert!(message.trailing);
+        assert_eq!(message, parse("005 n 1 2 3 4 5 6 7 8 9 10 11 12 13 :rest of  line").unwrap());
+    }
+
+    #[test]
+    fn equality_ignores_spelling() {
+        let message = parse("@b=x\\sy;a :n!u@h PRIVMSG  #c :hi").unwrap();
+        assert_eq!(message, parse("@a=;b=x\\sy :n!u@h PRIVMSG #c :hi").unwrap());
+        assert!(message != parse("@a=;b=x\\sy :n!u@h PRIVMSG #c hi").unwrap());
+        assert!(message != parse("@b=x\\sy :n!u@h PRIVMSG #c :hi").unwrap());
+    }
+}
@@ -0,0 +1,307 @@
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum Numeric {
+    Welcome((String, String)),
+    YourHost(String),
+    Created(String),
+    MyInfo((String, String, String, String)),
+    ISupport(Vec<String>),
+    LUserClient(String),
+    LUserOp(u64),
+    LUserUnknown(u64),
+    LUserChannels(u64),
+    LUserMe(String),
+    LocalUsers(String),
+    GlobalUsers(String),
+    Away((String, String)),
+    UserHost(Vec<String>),
+    IsOn(Vec<String>),
+    UnAway(String),
+    NowAway(String),
+    WhoisUser((String, String, String, String)),
+    WhoisServer((String, String, String)),
+    WhoisOperator(String),
+    EndOfWho(String),
+    WhoisIdle((String, u64, Option<i64>)),
+    EndOfWhois(String),
+    WhoisChannels((String, Vec<String>)),
+    ChannelModeIs((String, Vec<String>)),
+    CreationTime((String, i64)),
+    WhoisAccount((String, String)),
+    NoTopic(String),
+    Topic((String, String)),
+    TopicWhoTime((String, String, i64)),
+    Inviting((String, String)),
+    WhoReply((String, String, String, String, String, String, String)),
+    NamReply((String, String, Vec<String>)),
+    EndOfNames(String),
+    Motd(String),
+    MotdStart(String),
+    EndOfMotd(String),
+    HostHidden(String),
+    NoSuchNick((String, String)),
+    NoSuchServer((String, String)),
+    NoSuchChannel((String, String)),
+    CannotSendToChan((String, String)),
+    TooManyChannels((String, String)),
+    WasNoSuchNick((String, String)),
+    TooManyTargets((String, String)),
+    NoOrigin(String),
+    NoRecipient(String),
+    NoTextToSend(String),
+    UnknownCommand((String, String)),
+    NoMotd(String),
+    NoNicknameGiven(String),
+    ErroneousNickname((String, String)),
+    NicknameInUse((String, String)),
+    NickCollision((String, String)),
+    UnavailableResource((String, String)),
+    UserNotInChannel((String, String, String)),
+    NotOnChannel((String, String)),
+    UserOnChannel((String, String, String)),
+    NotRegistered(String),
+    NeedMoreParams((String, String)),
+    AlreadyRegistered(String),
+    PasswordMismatch(String),
+    YoureBannedCreep(String),
+    ChannelIsFull((String, String)),
+    UnknownMode((String, String)),
+    InviteOnlyChannel((String, String)),
+    BannedFromChannel((String, String)),
+    BadChannelKey((String, String)),
+    NoPrivileges(String),
+    ChanOpPrivsNeeded((String, String)),
+    UModeUnknownFlag(String),
+    UsersDontMatch(String),
+    MonOnline(Vec<String>),
+    MonOffline(Vec<String>),
+    LoggedIn((String, String, String)),
+    LoggedOut((String, String)),
+    NickLocked(String),
+    SaslSuccess(String),
+    SaslFail(String),
+    SaslTooLong(String),
+    SaslAborted(String),
+    SaslAlready(String),
+    SaslMechs(Vec<String>),
+    Other((u16, Vec<String>)),
+}
+
+pub fn parse(code: u16, params: &[&str]) -> Numeric {
+    let p = match params.len() {
+        0 => &params[..],
+        _ => &params[1..],
+    };
+    match code {
+        1 => Numeric::Welcome((first(params), last(p))),
+        2 => Numeric::YourHost(last(p)),
+        3 => Numeric::Created(last(p)),
+        4 => Numeric::MyInfo((arg(p, 0), arg(p, 1), arg(p, 2), arg(p, 3))),
+        5 => Numeric::ISupport(rest(&p[..p.len().saturating_sub(1)], 0)),
+        251 => Numeric::LUserClient(last(p)),
+        252 => Numeric::LUserOp(number(p, 0)),
+        253 => Numeric::LUserUnknown(number(p, 0)),
+        254 => Numeric::LUserChannels(number(p, 0)),
+        255 => Numeric::LUserMe(last(p)),
+        265 => Numeric::LocalUsers(last(p)),
+        266 => Numeric::GlobalUsers(last(p)),
+        301 => Numeric::Away((arg(p, 0), last(p))),
+        302 => Numeric::UserHost(words(p, 0)),
+        303 => Numeric::IsOn(words(p, 0)),
+        305 => Numeric::UnAway(last(p)),
+        306 => Numeric::NowAway(last(p)),
+        311 => Numeric::WhoisUser((arg(p, 0), arg(p, 1), arg(p, 2), last(p))),
+        312 => Numeric::WhoisServer((arg(p, 0), arg(p, 1), last(p))),
+        313 => Numeric::WhoisOperator(arg(p, 0)),
+        315 => Numeric::EndOfWho(arg(p, 0)),
+        317 => {
+            let signon = p.get(2).and_then(|s| s.parse::<i64>().ok());
+            Numeric::WhoisIdle((arg(p, 0), number(p, 1), signon))
+        },
+        318 => Numeric::EndOfWhois(arg(p, 0)),
+        319 => Numeric::WhoisChannels((arg(p, 0), words(p, 1))),
+        324 => Numeric::ChannelModeIs((arg(p, 0), rest(p, 1))),
+        329 => Numeric::CreationTime((arg(p, 0), number(p, 1) as i64)),
+        330 => Numeric::WhoisAccount((arg(p, 0), arg(p, 1))),
+        331 => Numeric::NoTopic(arg(p, 0)),
+        332 => Numeric::Topic((arg(p, 0), arg(p, 1))),
+        333 => Numeric::TopicWhoTime((arg(p, 0), arg(p, 1), number(p, 2) as i64)),
+        341 => Numeric::Inviting((arg(p, 0), arg(p, 1))),
+        352 => {
+            Numeric::WhoReply((arg(p, 0), arg(p, 1), arg(p, 2), arg(p, 3), arg(p, 4), arg(p, 5),
+                               last(p)))
+        },
+        353 => Numeric::NamReply((arg(p, 0), arg(p, 1), words(p, 2))),
+        366 => Numeric::EndOfNames(arg(p, 0)),
+        372 => Numeric::Motd(last(p)),
+        375 => Numeric::MotdStart(last(p)),
+        376 => Numeric::EndOfMotd(last(p)),
+        396 => Numeric::HostHidden(arg(p, 0)),
+        401 => Numeric::NoSuchNick(subject(p)),
+        402 => Numeric::NoSuchServer(subject(p)),
+        403 => Numeric::NoSuchChannel(subject(p)),
+        404 => Numeric::CannotSendToChan(subject(p)),
+        405 => Numeric::TooManyChannels(subject(p)),
+        406 => Numeric::WasNoSuchNick(subject(p)),
+        407 => Numeric::TooManyTargets(subject(p)),
+        409 => Numeric::NoOrigin(last(p)),
+        411 => Numeric::NoRecipient(last(p)),
+        412 => Numeric::NoTextToSend(last(p)),
+        421 => Numeric::UnknownCommand(subject(p)),
+        422 => Numeric::NoMotd(last(p)),
+        431 => Numeric::NoNicknameGiven(last(p)),
+        432 => Numeric::ErroneousNickname(subject(p)),
+        433 => Numeric::NicknameInUse(subject(p)),
+        436 => Numeric::NickCollision(subject(p)),
+        437 => Numeric::UnavailableResource(subject(p)),
+        441 => Numeric::UserNotInChannel((arg(p, 0), arg(p, 1), last(p))),
+        442 => Numeric::NotOnChannel(subject(p)),
+        443 => Numeric::UserOnChannel((arg(p, 0), arg(p, 1), last(p))),
+        451 => Numeric::NotRegistered(last(p)),
+        461 => Numeric::NeedMoreParams(subject(p)),
+        462 => Numeric::AlreadyRegistered(last(p)),
+        464 => Numeric::PasswordMismatch(last(p)),
+        465 => Numeric::YoureBannedCreep(last(p)),
+        471 => Numeric::ChannelIsFull(subject(p)),
+        472 => Numeric::UnknownMode(subject(p)),
+        473 => Numeric::InviteOnlyChannel(subject(p)),
+        474 => Numeric::BannedFromChannel(subject(p)),
+        475 => Numeric::BadChannelKey(subject(p)),
+        481 => Numeric::NoPrivileges(last(p)),
+        482 => Numeric::ChanOpPrivsNeeded(subject(p)),
+        501 => Numeric::UModeUnknownFlag(last(p)),
+        502 => Numeric::UsersDontMatch(last(p)),
+        730 => Numeric::MonOnline(list(p, 0)),
+        731 => Numeric::MonOffline(list(p, 0)),
+        900 => Numeric::LoggedIn((arg(p, 0), arg(p, 1), last(p))),
+        901 => Numeric::LoggedOut((arg(p, 0), last(p))),
+        902 => Numeric::NickLocked(last(p)),
+        903 => Numeric::SaslSuccess(last(p)),
+        904 => Numeric::SaslFail(last(p)),
+        905 => Numeric::SaslTooLong(last(p)),
+        906 => Numeric::SaslAborted(last(p)),
+        907 => Numeric::SaslAlready(last(p)),
+        908 => Numeric::SaslMechs(list(p, 0)),
+        _ => Numeric::Other((code, rest(params, 0))),
+    }
+}
+
+fn arg(params: &[&str], index: usize) -> String {
+    match params.get(index) {
+        Some(a) => a.to_string(),
+        None => "".to_string(),
+    }
+}
+
+fn first(params: &[&str]) -> String {
+    arg(params, 0)
+}
+
+fn last(params: &[&str]) -> String {
+    match params.last() {
+        Some(l) => l.to_string(),
+        None => "".to_string(),
+    }
+}
+
+fn rest(params: &[&str], index: usize) -> Vec<String> {
+    params.iter().skip(index).map(|p| p.to_string()).collect()
+}
+
+fn subject(params: &[&str]) -> (String, String) {
+    (arg(params, 0), last(params))
+}
+
+fn number(params: &[&str], index: usize) -> u64 {
+    arg(params, index).parse::<u64>().unwrap_or(0)
+}
+
+fn words(params: &[&str], index: usize) -> Vec<String> {
+    arg(params, index).split(' ').filter(|w| w.len() > 0).map(|w| w.to_string()).collect()
+}
+
+fn list(params: &[&str], index: usize) -> Vec<String> {
+    arg(params, index).split(',').filter(|w| w.len() > 0).map(|w| w.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Numeric};
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn registration() {
+        let welcome = "Welcome to the network bot!u@h";
+        assert_eq!(parse(1, &["bot", welcome]),
+                   Numeric::Welcome(("bot".to_string(), welcome.to_string())));
+        assert_eq!(parse(4, &["bot", "irc.example.net", "ircd-1.0", "iow", "beIklmnt"]),
+                   Numeric::MyInfo(("irc.example.net".to_string(), "ircd-1.0".to_string(),
+                                    "iow".to_string(), "beIklmnt".to_string())));
+        let isupport = ["bot", "CASEMAPPING=ascii", "NICKLEN=30", "are supported by this server"];
+        assert_eq!(parse(5, &isupport),
+                   Numeric::ISupport(strings(&["CASEMAPPING=ascii", "NICKLEN=30"])));
+        assert_eq!(parse(376, &["bot", "End of /MOTD command."]),
+                   Numeric::EndOfMotd("End of /MOTD command.".to_string()));
+        assert_eq!(parse(422, &["bot", "MOTD File is missing"]),
+                   Numeric::NoMotd("MOTD File is missing".to_string()));
+    }
+
+    #[test]
+    fn channel_replies() {
+        assert_eq!(parse(353, &["bot", "=", "#chan", "@op +voice  plain"]),
+                   Numeric::NamReply(("=".to_string(), "#chan".to_string(),
+                                      strings(&["@op", "+voice", "plain"]))));
+        assert_eq!(parse(366, &["bot", "#chan", "End of /NAMES list."]),
+                   Numeric::EndOfNames("#chan".to_string()));
+        assert_eq!(parse(324, &["bot", "#chan", "+kl", "key", "10"]),
+                   Numeric::ChannelModeIs(("#chan".to_string(), strings(&["+kl", "key", "10"]))));
+        assert_eq!(parse(333, &["bot", "#chan", "nick!u@h", "1700000000"]),
+                   Numeric::TopicWhoTime(("#chan".to_string(), "nick!u@h".to_string(),
+                                          1700000000)));
+        assert_eq!(parse(332, &["bot", "#chan", "the topic"]),
+                   Numeric::Topic(("#chan".to_string(), "the topic".to_string())));
+    }
+
+    #[test]
+    fn whois_replies() {
+        assert_eq!(parse(317, &["bot", "nick", "42", "1700000000", "seconds idle, signon time"]),
+                   Numeric::WhoisIdle(("nick".to_string(), 42, Some(1700000000))));
+        assert_eq!(parse(317, &["bot", "nick", "42", "seconds idle"]),
+                   Numeric::WhoisIdle(("nick".to_string(), 42, None)));
+        assert_eq!(parse(330, &["bot", "nick", "account", "is logged in as"]),
+                   Numeric::WhoisAccount(("nick".to_string(), "account".to_string())));
+    }
+
+    #[test]
+    fn errors_keep_their_subject() {
+        assert_eq!(parse(433, &["*", "korasho", "Nickname is already in use"]),
+                   Numeric::NicknameInUse(("korasho".to_string(),
+                                           "Nickname is already in use".to_string())));
+        assert_eq!(parse(474, &["bot", "#chan", "Cannot join channel (+b)"]),
+                   Numeric::BannedFromChannel(("#chan".to_string(),
+                                               "Cannot join channel (+b)".to_string())));
+        assert_eq!(parse(904, &["bot", "SASL authentication failed"]),
+                   Numeric::SaslFail("SASL authentication failed".to_string()));
+    }
+
+    #[test]
+    fn monitor_and_sasl_lists() {
+        assert_eq!(parse(731, &["bot", "korasho,other!u@h"]),
+                   Numeric::MonOffline(strings(&["korasho", "other!u@h"])));
+        assert_eq!(parse(908, &["bot", "PLAIN,EXTERNAL", "are available SASL mechanisms"]),
+                   Numeric::SaslMechs(strings(&["PLAIN", "EXTERNAL"])));
+    }
+
+    #[test]
+    fn malformed_and_unknown_numerics() {
+        assert_eq!(parse(252, &["bot", "many", "operators online"]), Numeric::LUserOp(0));
+        assert_eq!(parse(366, &[]), Numeric::EndOfNames("".to_string()));
+        assert_eq!(parse(5, &["bot"]), Numeric::ISupport(Vec::new()));
+        assert_eq!(parse(999, &["bot", "a", "b c"]),
+                   Numeric::Other((999, strings(&["bot", "a", "b c"]))));
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+use openssl::crypto::{memcmp, pkcs5, rand};
+
+const SCHEME: &'static str = "pbkdf2-sha1";
+const DEFAULT_ITERATIONS: usize = 100000;
+// Anything past this would stall the bot for seconds on every login attempt.
+const MAX_ITERATIONS: usize = 10000000;
+const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 20;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct PasswordHash {
+    iterations: usize,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl PasswordHash {
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = pkcs5::pbkdf2_hmac_sha1(password, &self.salt, self.iterations, self.hash.len());
+        hash.len() == self.hash.len() && memcmp::eq(&hash, &self.hash)
+    }
+}
+
+impl fmt::Display for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{scheme}${iterations}${salt}${hash}",
+               scheme=SCHEME,
+               iterations=self.iterations,
+               salt=to_hex(&self.salt),
+               hash=to_hex(&self.hash))
+    }
+}
+
+pub fn hash(password: &str) -> PasswordHash {
+    let salt = rand::rand_bytes(SALT_LENGTH);
+    let hash = pkcs5::pbkdf2_hmac_sha1(password, &salt, DEFAULT_ITERATIONS, HASH_LENGTH);
+    PasswordHash {
+        iterations: DEFAULT_ITERATIONS,
+        salt: salt,
+        hash: hash,
+    }
+}
+
+pub fn parse(value: &str) -> Result<PasswordHash, String> {
+    let parts: Vec<&str> = value.split('$').collect();
+    if parts.len() != 4 || parts[0] != SCHEME {
+        return Err(format!("Password hashes look like {scheme}$iterations$salt$hash",
+                           scheme=SCHEME));
+    }
+    let iterations = match parts[1].parse::<usize>() {
+        Ok(i) if i > 0 && i <= MAX_ITERATIONS => i,
+        _ => return Err(format!("Invalid iteration count {count}", count=parts[1])),
+    };
+    let salt = match from_hex(parts[2]) {
+        Some(s) => s,
+        None => return Err(format!("Invalid salt {salt}", salt=parts[2])),
+    };
+    let hash = match from_hex(parts[3]) {
+        Some(h) if h.len() > 0 => h,
+        _ => return Err(format!("Invalid hash {hash}", hash=parts[3])),
+    };
+    Ok(PasswordHash {
+        iterations: iterations,
+        salt: salt,
+        hash: hash,
+    })
+}
+
+pub fn parse_list(input: &str) -> Vec<PasswordHash> {
+    input.lines()
+        .map(|l| l.trim())
+        .filter(|l| l.len() > 0 && !l.starts_with('#'))
+        .filter_map(|l| parse(l).ok())
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}", b=b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(hex.len() / 2);
+    for i in (0..hex.len()).step_by(2) {
+        match u8::from_str_radix(&hex[i..i + 2], 16) {
+            Ok(b) => bytes.push(b),
+            Err(_) => return None,
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash, parse, parse_list};
+
+    const PASSWORD_HASH: &'static str = "pbkdf2-sha1$100000$454f803b76b85b818e0773315f499b86$\
+                                         8f4005a75e8b9795466a4f639a4e544ab822886a";
+
+    #[test]
+    fn verifies_a_known_hash() {
+        let hash = parse(PASSWORD_HASH).unwrap();
+        assert!(hash.verify("password"));
+        assert!(!hash.verify("Password"));
+        assert!(!hash.verify(""));
+        assert_eq!(hash.to_string(), PASSWORD_HASH);
+    }
+
+    #[test]
+    fn hashes_round_trip() {
+        let hashed = hash("hunter2");
+        let parsed = parse(&hashed.to_string()).unwrap();
+        assert_eq!(parsed, hashed);
+        assert!(parsed.verify("hunter2"));
+        assert!(!parsed.verify("hunter3"));
+    }
+
+    #[test]
+    fn uppercase_hex_is_accepted() {
+        assert_eq!(parse(&PASSWORD_HASH.to_uppercase().replace("PBKDF2-SHA1", "pbkdf2-sha1")),
+                   parse(PASSWORD_HASH));
+    }
+
+    #[test]
+    fn malformed_hashes() {
+        let malformed = [
+            "",
+            "password",
+            "bcrypt$10$00$00",
+            "pbkdf2-sha1$0$00$00",
+            "pbkdf2-sha1$many$00$00",
+            "pbkdf2-sha1$10000001$00$00",
+            "pbkdf2-sha1$18446744073709551615$00$00",
+            "pbkdf2-sha1$1000$0g$00",
+            "pbkdf2-sha1$1000$000$00",
+            "pbkdf2-sha1$1000$00$",
+            "pbkdf2-sha1$1000$00$00$00",
+        ];
+        for value in malformed.iter() {
+            assert!(parse(value).is_err(), "{:?}", value);
+        }
+        assert!(parse("pbkdf2-sha1$10000000$00$00").is_ok());
+    }
+
+    #[test]
+    fn revoked_lists_skip_junk() {
+        let list = format!("# revoked\n\n{hash}\r\nnot a hash\n  {hash}  \n", hash=PASSWORD_HASH);
+        let hashes = parse_list(&list);
+        assert_eq!(hashes.len(), 2);
+        assert!(hashes.contains(&parse(PASSWORD_HASH).unwrap()));
+    }
+}
@@ -0,0 +1,196 @@
+use std::fmt;
+use casemap::CaseMapping;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+#[derive(Eq)]
+#[derive(PartialOrd)]
+#[derive(Ord)]
+pub enum Role {
+    Ignored,
+    User,
+    Trusted,
+    Op,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn from_name(name: &str) -> Option<Role> {
+        match &*name.to_lowercase() {
+            "ignored" => Some(Role::Ignored),
+            "user" => Some(Role::User),
+            "trusted" => Some(Role::Trusted),
+            "op" => Some(Role::Op),
+            "admin" => Some(Role::Admin),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Role::Ignored => write!(f, "ignored"),
+            &Role::User => write!(f, "user"),
+            &Role::Trusted => write!(f, "trusted"),
+            &Role::Op => write!(f, "op"),
+            &Role::Admin => write!(f, "admin"),
+            &Role::Owner => write!(f, "owner"),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    pub masks: Vec<String>,
+    pub accounts: Vec<String>,
+}
+
+impl User {
+    pub fn matches(&self, client: &::irc::Client, account: Option<&str>,
+                   casemapping: CaseMapping) -> bool {
+        if let Some(account) = account {
+            if self.accounts.iter().any(|a| casemapping.equals(a, account)) {
+                return true;
+            }
+        }
+        let mask = format!("{nick}!{username}@{address}",
+                           nick=client.nick,
+                           username=client.username,
+                           address=client.address);
+        let mask = casemapping.to_lower(&mask);
+        self.masks.iter().any(|m| glob_match(&casemapping.to_lower(m), &mask))
+    }
+}
+
+pub fn role_of(users: &[User], client: &::irc::Client, account: Option<&str>,
+               casemapping: CaseMapping) -> Role {
+    let matched: Vec<Role> = users.iter()
+        .filter(|u| u.matches(client, account, casemapping))
+        .map(|u| u.role)
+        .collect();
+    if matched.contains(&Role::Ignored) {
+        return Role::Ignored;
+    }
+    matched.into_iter().max().unwrap_or(Role::User)
+}
+
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let mut p = 0;
+    let mut t = 0;
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use casemap::CaseMapping;
+    use irc::Client;
+    use super::{glob_match, role_of, Role, User};
+
+    fn client(nick: &str, username: &str, address: &str) -> Client<'static> {
+        Client {
+            nick: nick.to_string().into(),
+            username: username.to_string().into(),
+            address: address.to_string().into(),
+        }
+    }
+
+    fn user(role: Role, masks: &[&str], accounts: &[&str]) -> User {
+        User {
+            name: "someone".to_string(),
+            role: role,
+            masks: masks.iter().map(|m| m.to_string()).collect(),
+            accounts: accounts.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "nick!user@host"));
+        assert!(glob_match("nick!*@*", "nick!user@host"));
+        assert!(glob_match("*!*@*.example.com", "a!b@c.d.example.com"));
+        assert!(!glob_match("*!*@*.example.com", "a!b@example.com"));
+        assert!(glob_match("n?ck!*", "nick!u@h"));
+        assert!(!glob_match("n?ck!*", "nck!u@h"));
+        assert!(glob_match("*a*b*c", "xxaxxbxxbxxc"));
+        assert!(!glob_match("*a*b*c", "xxaxxbxxcxxb"));
+        assert!(glob_match("**!**", "!"));
+        assert!(!glob_match("", "x"));
+        assert!(!glob_match("nick", "nickname"));
+        assert!(glob_match("*@2001:db8::*", "n!u@2001:db8::1"));
+    }
+
+    #[test]
+    fn masks_follow_the_casemapping() {
+        let admin = user(Role::Admin, &["[Ko]!*@*.Example.COM"], &[]);
+        let visitor = client("{ko}", "u", "host.example.com");
+        assert!(admin.matches(&visitor, None, CaseMapping::Rfc1459));
+        assert!(!admin.matches(&visitor, None, CaseMapping::Ascii));
+    }
+
+    #[test]
+    fn accounts_match_without_a_mask() {
+        let admin = user(Role::Admin, &[], &["Korasho"]);
+        let visitor = client("anyone", "u", "h");
+        assert!(admin.matches(&visitor, Some("korasho"), CaseMapping::Rfc1459));
+        assert!(!admin.matches(&visitor, None, CaseMapping::Rfc1459));
+    }
+
+    #[test]
+    fn highest_matching_role_wins() {
+        let users = [user(Role::Ignored, &["*!*@spam.example"], &[]),
+                     user(Role::Trusted, &["friend!*@*"], &[]),
+                     user(Role::Owner, &[], &["boss"])];
+        let rfc1459 = CaseMapping::Rfc1459;
+        assert_eq!(role_of(&users, &client("friend", "u", "h"), None, rfc1459), Role::Trusted);
+        assert_eq!(role_of(&users, &client("friend", "u", "h"), Some("boss"), rfc1459),
+                   Role::Owner);
+        assert_eq!(role_of(&users, &client("x", "u", "spam.example"), None, rfc1459),
+                   Role::Ignored);
+        assert_eq!(role_of(&users, &client("x", "u", "h"), None, rfc1459), Role::User);
+        assert_eq!(Role::from_name("OP"), Some(Role::Op));
+        assert_eq!(Role::from_name("root"), None);
+    }
+
+    #[test]
+    fn ignored_beats_any_other_match() {
+        let users = [user(Role::Trusted, &["friend!*@*"], &[]),
+                     user(Role::Ignored, &["*!*@spam.example"], &[]),
+                     user(Role::Owner, &[], &["boss"])];
+        let rfc1459 = CaseMapping::Rfc1459;
+        assert_eq!(role_of(&users, &client("friend", "u", "spam.example"), None, rfc1459),
+                   Role::Ignored);
+        assert_eq!(role_of(&users, &client("x", "u", "spam.example"), Some("boss"), rfc1459),
+                   Role::Ignored);
+        assert_eq!(role_of(&users, &client("friend", "u", "h"), None, rfc1459), Role::Trusted);
+    }
+}
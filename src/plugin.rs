@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::fmt;
+use permissions::Role;
+
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+pub enum Arg {
+    Required(&'static str),
+    Optional(&'static str),
+    Rest(&'static str),
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub help: &'static str,
+    pub args: &'static [Arg],
+    pub role: Role,
+}
+
+pub struct Invocation<'a> {
+    pub name: &'a str,
+    pub args: Vec<String>,
+    pub message: &'a ::irc::IRCServerMessage<'a>,
+    pub conn_state: &'a ::irc::ConnectionState,
+    pub registry: &'a Registry,
+}
+
+impl<'a> Invocation<'a> {
+    pub fn arg(&self, index: usize) -> Option<&str> {
+        self.args.get(index).map(|a| a.as_str())
+    }
+
+    pub fn reply(&self, bot: &mut ::bot::Bot, message: &str) {
+        bot.reply(self.message, message, self.conn_state);
+    }
+
+    pub fn styled_reply(&self, bot: &mut ::bot::Bot, message: &::formatting::Formatter) {
+        bot.styled_msg(self.message, message, self.conn_state);
+    }
+
+    pub fn action(&self, bot: &mut ::bot::Bot, action: &str) {
+        let target = self.message.target.to_string();
+        bot.action(target, self.message.from.clone(), action, self.conn_state);
+    }
+
+    pub fn notice(&self, bot: &mut ::bot::Bot, message: &str) {
+        if let Some(::irc::Entity::Client(ref c)) = self.message.from {
+            bot.push_job(::bot::BotJob::Notice((c.nick.to_string(), message.to_string())));
+        }
+    }
+}
+
+pub trait Command {
+    fn spec(&self) -> CommandSpec;
+    fn run(&self, bot: &mut ::bot::Bot, invocation: &Invocation);
+}
+
+pub trait Plugin {
+    fn name(&self) -> &'static str;
+
+    fn commands(&self) -> Vec<Box<dyn Command>> {
+        Vec::new()
+    }
+
+    fn subscriptions(&self) -> Vec<::event::Subscription> {
+        Vec::new()
+    }
+
+    fn capabilities(&self) -> Vec<&'static str> {
+        Vec::new()
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    commands: Vec<(&'static str, Box<dyn Command>)>,
+    names: HashMap<String, usize>,
+    subscriptions: Vec<::event::Subscription>,
+    capabilities: Vec<&'static str>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.commands.iter().map(|&(_, ref c)| c.spec().name).collect();
+        write!(f, "Registry {:?}", names)
+    }
+}
+
+impl Registry {
+    pub fn register(&mut self, plugin: Box<dyn Plugin>) {
+        for command in plugin.commands() {
+            let spec = command.spec();
+            let index = self.commands.len();
+            for name in Some(&spec.name).into_iter().chain(spec.aliases.iter()) {
+                let name = name.to_lowercase();
+                if self.names.contains_key(&name) {
+                    println!("{plugin}: command {name} is already registered",
+                             plugin=plugin.name(),
+                             name=name);
+                    continue;
+                }
+                self.names.insert(name, index);
+            }
+            self.commands.push((plugin.name(), command));
+        }
+        self.subscriptions.extend(plugin.subscriptions());
+        for capability in plugin.capabilities() {
+            if !self.capabilities.contains(&capability) {
+                self.capabilities.push(capability);
+            }
+        }
+    }
+
+    pub fn capabilities(&self) -> &[&'static str] {
+        &self.capabilities
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Command> {
+        match self.names.get(&name.to_lowercase()) {
+            Some(i) => Some(&*self.commands[*i].1),
+            None => None,
+        }
+    }
+
+    pub fn specs(&self) -> Vec<(&'static str, CommandSpec)> {
+        self.commands.iter().map(|&(plugin, ref c)| (plugin, c.spec())).collect()
+    }
+
+    pub fn dispatch_event(&self, event: &::event::Event, networks: &[&str],
+                          conn_state: &::irc::ConnectionState) -> ::event::Outcome {
+        let mut outcome = ::event::Outcome::default();
+        for subscription in self.subscriptions.iter() {
+            if !subscription.matches(event, networks, conn_state) {
+                continue;
+            }
+            let result = subscription.hook.handle(event, conn_state);
+            outcome.jobs.extend(result.jobs);
+            if result.stop {
+                outcome.stop = true;
+                break;
+            }
+        }
+        outcome
+    }
+
+    fn suggest(&self, bot: &::bot::Bot, name: &str, message: &::irc::IRCServerMessage,
+               conn_state: &::irc::ConnectionState) -> Option<&'static str> {
+        let name = name.to_lowercase();
+        let mut best = None;
+        for &(_, ref command) in self.commands.iter() {
+            let spec = command.spec();
+            if !bot.authed(message, spec.role, conn_state) {
+                continue;
+            }
+            for candidate in Some(&spec.name).into_iter().chain(spec.aliases.iter()) {
+                let distance = edit_distance(&name, candidate);
+                if distance > MAX_SUGGESTION_DISTANCE || distance >= candidate.len() {
+                    continue;
+                }
+                match best {
+                    Some((d, c)) if (d, c) <= (distance, *candidate) => {},
+                    _ => best = Some((distance, *candidate)),
+                }
+            }
+        }
+        best.map(|(_, c)| c)
+    }
+
+    pub fn dispatch(&self, bot: &mut ::bot::Bot, name: &str, args: &str,
+                    message: &::irc::IRCServerMessage, conn_state: &::irc::ConnectionState) {
+        let command = match self.get(name) {
+            Some(c) => c,
+            None => {
+                // Channels are often shared with other bots using the same prefix, so only
+                // answer when the name looks like a typo of one of our own commands.
+                if let Some(suggestion) = self.suggest(bot, name, message, conn_state) {
+                    let reply = format!("Unknown command {prefix}{name}, did you mean \
+                                         {prefix}{suggestion}? See {prefix}help.",
+                                        prefix=bot.command_prefix(),
+                                        name=name,
+                                        suggestion=suggestion);
+                    bot.reply(message, &reply, conn_state);
+                }
+                return;
+            },
+        };
+        let spec = command.spec();
+        if !bot.authed(message, spec.role, conn_state) {
+            println!("Not permitted to run {name}, needs {role}", name=spec.name, role=spec.role);
+            return;
+        }
+        let args = match parse_args(&spec, args) {
+            Some(a) => a,
+            None => {
+                let usage = format!("Usage: {usage}", usage=usage(bot.command_prefix(), &spec));
+                bot.reply(message, &usage, conn_state);
+                return;
+            },
+        };
+        let invocation = Invocation {
+            name: name,
+            args: args,
+            message: message,
+            conn_state: conn_state,
+            registry: self,
+        };
+        command.run(bot, &invocation);
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, y) in b.iter().enumerate() {
+            let substitution = previous[j] + match x == *y {
+                true => 0,
+                false => 1,
+            };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+pub fn parse_args(spec: &CommandSpec, args: &str) -> Option<Vec<String>> {
+    let mut parsed = Vec::new();
+    let mut rest = args.trim();
+    for arg in spec.args.iter() {
+        if rest.len() < 1 {
+            match *arg {
+                Arg::Optional(_) => break,
+                _ => return None,
+            }
+        }
+        if let Arg::Rest(_) = *arg {
+            parsed.push(rest.to_string());
+            break;
+        }
+        let end = rest.find(' ').unwrap_or(rest.len());
+        parsed.push(rest[0..end].to_string());
+        rest = rest[end..].trim_start();
+    }
+    Some(parsed)
+}
+
+pub fn usage(prefix: char, spec: &CommandSpec) -> String {
+    let mut usage = format!("{prefix}{name}", prefix=prefix, name=spec.name);
+    for arg in spec.args.iter() {
+        let arg = match *arg {
+            Arg::Required(name) => format!(" <{name}>", name=name),
+            Arg::Optional(name) => format!(" [{name}]", name=name),
+            Arg::Rest(name) => format!(" <{name}...>", name=name),
+        };
+        usage.push_str(&arg);
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use permissions::Role;
+    use super::{edit_distance, parse_args, usage, Arg, CommandSpec};
+
+    const ARGS: [Arg; 3] = [Arg::Required("user"), Arg::Optional("role"), Arg::Rest("reason")];
+
+    fn spec(args: &'static [Arg]) -> CommandSpec {
+        CommandSpec {
+            name: "grant",
+            aliases: &[],
+            help: "",
+            args: args,
+            role: Role::Admin,
+        }
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("help", "help"), 0);
+        assert_eq!(edit_distance("hlep", "help"), 2);
+        assert_eq!(edit_distance("botsnak", "botsnack"), 1);
+        assert_eq!(edit_distance("", "more"), 4);
+        assert_eq!(edit_distance("whoam1", "whoami"), 1);
+    }
+
+    #[test]
+    fn arguments_follow_the_spec() {
+        let grant = spec(&ARGS);
+        assert_eq!(parse_args(&grant, ""), None);
+        assert_eq!(parse_args(&grant, "  alice "), Some(vec!["alice".to_string()]));
+        assert_eq!(parse_args(&grant, "alice op"), None);
+        assert_eq!(parse_args(&grant, "alice op  was  helpful "),
+                   Some(vec!["alice".to_string(), "op".to_string(), "was  helpful".to_string()]));
+        let optional = spec(&[Arg::Optional("topic")]);
+        assert_eq!(parse_args(&optional, ""), Some(Vec::new()));
+        assert_eq!(parse_args(&optional, "auth extra"), Some(vec!["auth".to_string()]));
+    }
+
+    #[test]
+    fn usage_lines() {
+        assert_eq!(usage('!', &spec(&ARGS)), "!grant <user> [role] <reason...>");
+        assert_eq!(usage('.', &spec(&[])), ".grant");
+    }
+}
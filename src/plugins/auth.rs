@@ -0,0 +1,184 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use plugin::{Arg, Command, CommandSpec, Invocation, Plugin};
+use permissions::Role;
+
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT_SECONDS: i64 = 600;
+
+pub struct Auth;
+
+impl Plugin for Auth {
+    fn name(&self) -> &'static str {
+        "auth"
+    }
+
+    fn commands(&self) -> Vec<Box<dyn Command>> {
+        let auth = AuthCommand { failures: RefCell::new(HashMap::new()) };
+        vec![Box::new(auth), Box::new(LogoutCommand), Box::new(WhoamiCommand)]
+    }
+
+    fn capabilities(&self) -> Vec<&'static str> {
+        vec![::capabilities::ACCOUNT_TAG]
+    }
+}
+
+struct AuthCommand {
+    failures: RefCell<HashMap<String, (u32, i64)>>,
+}
+
+impl AuthCommand {
+    fn locked(&self, key: &str, now: i64) -> bool {
+        match self.failures.borrow().get(key) {
+            Some(&(count, last)) => count >= MAX_FAILURES && now - last < LOCKOUT_SECONDS,
+            None => false,
+        }
+    }
+
+    fn fail(&self, key: String, now: i64) {
+        let mut failures = self.failures.borrow_mut();
+        failures.retain(|_, &mut (_, last)| now - last < LOCKOUT_SECONDS);
+        let failure = failures.entry(key).or_insert((0, now));
+        failure.0 += 1;
+        failure.1 = now;
+    }
+
+    fn verify(&self, bot: &::bot::Bot, password: &str) -> bool {
+        !bot.config().admin_password_revoked() && bot.config().admin_password.verify(password)
+    }
+}
+
+impl Command for AuthCommand {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec {
+            name: "auth",
+            aliases: &[],
+            help: "Authenticate as the bot's admin. Only works in a private message.",
+            args: &[Arg::Rest("password")],
+            role: Role::User,
+        }
+    }
+
+    fn run(&self, bot: &mut ::bot::Bot, invocation: &Invocation) {
+        let message = invocation.message;
+        let client = match message.from {
+            Some(::irc::Entity::Client(ref c)) => c,
+            _ => return,
+        };
+        let password = invocation.arg(0).unwrap_or("");
+        // Idents are up to the client, so a rotating username mustn't reset the count.
+        let key = match ::bot::account_of(message, invocation.conn_state) {
+            Some(account) => format!("account {account}", account=account),
+            None => client.address.to_string(),
+        }.to_lowercase();
+        let now = ::time::get_time().sec;
+        let isupport = &invocation.conn_state.isupport;
+        if isupport.is_channel(isupport.strip_statusmsg(message.target)) {
+            if self.verify(bot, password) {
+                if let Err(err) = bot.config().revoke_admin_password() {
+                    println!("Unable to record the revoked admin password: {err}", err=err);
+                }
+                bot.clear_sessions();
+                println!("Warning: {nick} sent the admin password to {channel}. It no longer \
+                          works, and the bot won't start again until a new admin_password_hash \
+                          is set.",
+                         nick=client.nick,
+                         channel=message.target);
+                invocation.reply(bot, "That password was just sent in public, so it no longer \
+                                       works. Please set a new one.");
+                return;
+            }
+            println!("Public auth attempt from {nick}!{username}@{address}",
+                     nick=client.nick,
+                     username=client.username,
+                     address=client.address);
+            self.fail(key, now);
+            invocation.reply(bot, "Only auth in a private message.");
+            return;
+        }
+        if self.locked(&key, now) {
+            invocation.reply(bot, "Too many failed attempts. Try again later.");
+            return;
+        }
+        if self.verify(bot, password) {
+            self.failures.borrow_mut().remove(&key);
+            bot.start_session(message, invocation.conn_state);
+            invocation.reply(bot, "Authed!");
+            return;
+        }
+        println!("Failed auth attempt from {nick}!{username}@{address}",
+                 nick=client.nick,
+                 username=client.username,
+                 address=client.address);
+        self.fail(key, now);
+        invocation.reply(bot, "Not authed. :(");
+    }
+}
+
+struct LogoutCommand;
+
+impl Command for LogoutCommand {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec {
+            name: "logout",
+            aliases: &["deauth"],
+            help: "End your auth session.",
+            args: &[],
+            role: Role::User,
+        }
+    }
+
+    fn run(&self, bot: &mut ::bot::Bot, invocation: &Invocation) {
+        let nick = match invocation.message.from {
+            Some(::irc::Entity::Client(ref c)) => c.nick.clone(),
+            _ => return,
+        };
+        if bot.session(invocation.message, invocation.conn_state).is_some() &&
+            bot.end_session(&nick, invocation.conn_state) {
+            invocation.reply(bot, "Logged out.");
+            return;
+        }
+        invocation.reply(bot, "You aren't authed.");
+    }
+}
+
+struct WhoamiCommand;
+
+impl Command for WhoamiCommand {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec {
+            name: "whoami",
+            aliases: &[],
+            help: "Privately show who the bot thinks you are and what you're allowed to do.",
+            args: &[],
+            role: Role::User,
+        }
+    }
+
+    fn run(&self, bot: &mut ::bot::Bot, invocation: &Invocation) {
+        let message = invocation.message;
+        let client = match message.from {
+            Some(::irc::Entity::Client(ref c)) => c,
+            _ => return,
+        };
+        let role = bot.role(message, invocation.conn_state);
+        let account = match ::bot::account_of(message, invocation.conn_state) {
+            Some(a) => format!(", logged in as {account}", account=a),
+            None => String::new(),
+        };
+        let session = match bot.session(message, invocation.conn_state) {
+            Some(s) => format!(", authed as {role} for {minutes}m",
+                               role=s.role,
+                               minutes=(message.time - s.started) / 60),
+            None => String::new(),
+        };
+        let reply = format!("{nick}!{username}@{address}: {role}{account}{session}",
+                            nick=client.nick,
+                            username=client.username,
+                            address=client.address,
+                            role=role,
+                            account=account,
+                            session=session);
+        invocation.notice(bot, &reply);
+    }
+}
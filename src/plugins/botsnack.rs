@@ -0,0 +1,32 @@
+use plugin::{Command, CommandSpec, Invocation, Plugin};
+use permissions::Role;
+
+pub struct Botsnack;
+
+impl Plugin for Botsnack {
+    fn name(&self) -> &'static str {
+        "botsnack"
+    }
+
+    fn commands(&self) -> Vec<Box<dyn Command>> {
+        vec![Box::new(BotsnackCommand)]
+    }
+}
+
+struct BotsnackCommand;
+
+impl Command for BotsnackCommand {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec {
+            name: "botsnack",
+            aliases: &[],
+            help: "Feed the bot.",
+            args: &[],
+            role: Role::Admin,
+        }
+    }
+
+    fn run(&self, bot: &mut ::bot::Bot, invocation: &Invocation) {
+        invocation.action(bot, "munches happily :)");
+    }
+}
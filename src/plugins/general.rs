@@ -0,0 +1,78 @@
+use plugin::{Arg, Command, CommandSpec, Invocation, Plugin};
+use permissions::Role;
+
+pub struct General;
+
+impl Plugin for General {
+    fn name(&self) -> &'static str {
+        "general"
+    }
+
+    fn commands(&self) -> Vec<Box<dyn Command>> {
+        vec![Box::new(HelpCommand), Box::new(MoreCommand)]
+    }
+}
+
+struct HelpCommand;
+
+impl Command for HelpCommand {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec {
+            name: "help",
+            aliases: &["commands"],
+            help: "List commands, or describe one.",
+            args: &[Arg::Optional("command")],
+            role: Role::User,
+        }
+    }
+
+    fn run(&self, bot: &mut ::bot::Bot, invocation: &Invocation) {
+        let prefix = bot.command_prefix();
+        let name = match invocation.arg(0) {
+            Some(n) => n.trim_start_matches(prefix),
+            None => {
+                let names: Vec<&str> = invocation.registry.specs().iter()
+                    .map(|&(_, ref s)| s.name)
+                    .collect();
+                let reply = format!("Commands: {names}", names=names.join(", "));
+                invocation.reply(bot, &reply);
+                return;
+            },
+        };
+        let spec = match invocation.registry.get(name) {
+            Some(c) => c.spec(),
+            None => {
+                invocation.reply(bot, &format!("No such command: {name}", name=name));
+                return;
+            },
+        };
+        let reply = ::formatting::new()
+            .bold(&::plugin::usage(prefix, &spec))
+            .text(" - ")
+            .text(spec.help);
+        invocation.styled_reply(bot, &reply);
+    }
+}
+
+struct MoreCommand;
+
+impl Command for MoreCommand {
+    fn spec(&self) -> CommandSpec {
+        CommandSpec {
+            name: "more",
+            aliases: &[],
+            help: "Show the next page of a long reply.",
+            args: &[],
+            role: Role::User,
+        }
+    }
+
+    fn run(&self, bot: &mut ::bot::Bot, invocation: &Invocation) {
+        let message = invocation.message;
+        let target = ::bot::reply_target(message.target.to_string(), message.from.clone(),
+                                         invocation.conn_state);
+        if let Some(target) = target {
+            bot.push_job(::bot::BotJob::More(target));
+        }
+    }
+}
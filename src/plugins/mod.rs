@@ -0,0 +1,11 @@
+mod auth;
+mod botsnack;
+mod general;
+
+pub fn registry() -> ::plugin::Registry {
+    let mut registry = ::plugin::Registry::default();
+    registry.register(Box::new(general::General));
+    registry.register(Box::new(auth::Auth));
+    registry.register(Box::new(botsnack::Botsnack));
+    registry
+}
@@ -0,0 +1,121 @@
+pub const SASL: &'static str = "sasl";
+pub const CHUNK_SIZE: usize = 400;
+
+const BASE64_CHARS: &'static [u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let b0 = chunk[0] as usize;
+        let b1 = *chunk.get(1).unwrap_or(&0) as usize;
+        let b2 = *chunk.get(2).unwrap_or(&0) as usize;
+        encoded.push(BASE64_CHARS[b0 >> 2] as char);
+        encoded.push(BASE64_CHARS[((b0 & 0x03) << 4) | (b1 >> 4)] as char);
+        match chunk.len() {
+            1 => encoded.push_str("=="),
+            2 => {
+                encoded.push(BASE64_CHARS[((b1 & 0x0f) << 2) | (b2 >> 6)] as char);
+                encoded.push('=');
+            },
+            _ => {
+                encoded.push(BASE64_CHARS[((b1 & 0x0f) << 2) | (b2 >> 6)] as char);
+                encoded.push(BASE64_CHARS[b2 & 0x3f] as char);
+            },
+        }
+    }
+    encoded
+}
+
+pub fn payload(sasl: &::config::Sasl) -> Vec<u8> {
+    match sasl.mechanism {
+        ::config::SaslMechanism::Plain => {
+            let plain = format!("{account}\0{account}\0{password}",
+                                account=sasl.account,
+                                password=sasl.password);
+            plain.into_bytes()
+        },
+        ::config::SaslMechanism::External => sasl.account.clone().into_bytes(),
+    }
+}
+
+pub fn authenticate_messages(sasl: &::config::Sasl) -> Vec<String> {
+    let encoded = encode_base64(&payload(sasl));
+    let mut messages: Vec<String> = encoded.as_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|c| String::from_utf8_lossy(c).into_owned())
+        .collect();
+    if encoded.len() % CHUNK_SIZE == 0 {
+        messages.push("+".to_string());
+    }
+    messages
+}
+
+pub fn supports_mechanism(cap_value: Option<&String>, mechanism: &::config::SaslMechanism) -> bool {
+    let cap_value = match cap_value {
+        Some(v) if v.len() > 0 => v,
+        _ => return true,
+    };
+    let mechanism = mechanism.to_string();
+    cap_value.split(',').any(|m| m.eq_ignore_ascii_case(&mechanism))
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{Sasl, SaslMechanism};
+    use super::{authenticate_messages, encode_base64, payload, supports_mechanism, CHUNK_SIZE};
+
+    fn external(account: &str) -> Sasl {
+        Sasl {
+            mechanism: SaslMechanism::External,
+            account: account.to_string(),
+            password: "".to_string(),
+            required: true,
+        }
+    }
+
+    #[test]
+    fn rfc4648_vectors() {
+        let vectors = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"),
+                       ("foob", "Zm9vYg=="), ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")];
+        for &(input, encoded) in vectors.iter() {
+            assert_eq!(encode_base64(input.as_bytes()), encoded);
+        }
+        assert_eq!(encode_base64(&[0xfb, 0xff, 0xbf]), "+/+/");
+    }
+
+    #[test]
+    fn plain_payload() {
+        let sasl = Sasl {
+            mechanism: SaslMechanism::Plain,
+            account: "korasho".to_string(),
+            password: "hunter2".to_string(),
+            required: true,
+        };
+        assert_eq!(payload(&sasl), b"korasho\0korasho\0hunter2".to_vec());
+        assert_eq!(authenticate_messages(&sasl), vec!["a29yYXNobwBrb3Jhc2hvAGh1bnRlcjI="]);
+    }
+
+    #[test]
+    fn chunks_of_400_bytes() {
+        let lengths = |raw: usize| -> Vec<usize> {
+            authenticate_messages(&external(&"a".repeat(raw))).iter().map(|m| m.len()).collect()
+        };
+        assert_eq!(lengths(297), vec![396]);
+        assert_eq!(lengths(299), vec![CHUNK_SIZE, 1]);
+        assert_eq!(lengths(300), vec![CHUNK_SIZE, 1]);
+        assert_eq!(lengths(301), vec![CHUNK_SIZE, 4]);
+        assert_eq!(lengths(600), vec![CHUNK_SIZE, CHUNK_SIZE, 1]);
+        assert_eq!(authenticate_messages(&external(&"a".repeat(300)))[1], "+");
+        assert_eq!(authenticate_messages(&external("")), vec!["+"]);
+    }
+
+    #[test]
+    fn mechanisms_from_the_cap_value() {
+        let plain = SaslMechanism::Plain;
+        assert!(supports_mechanism(None, &plain));
+        assert!(supports_mechanism(Some(&"".to_string()), &plain));
+        assert!(supports_mechanism(Some(&"EXTERNAL,plain".to_string()), &plain));
+        assert!(!supports_mechanism(Some(&"EXTERNAL".to_string()), &plain));
+    }
+}
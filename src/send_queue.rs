@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+use time;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Copy)]
+#[derive(PartialEq)]
+pub enum Priority {
+    Immediate,
+    High,
+    Normal,
+}
+
+pub fn priority(command: &str) -> Priority {
+    match command {
+        "PONG" | "PING" | "CAP" | "AUTHENTICATE" | "PASS" | "NICK" | "USER" | "QUIT" => Priority::Immediate,
+        "PRIVMSG" | "NOTICE" | "TAGMSG" => Priority::Normal,
+        _ => Priority::High,
+    }
+}
+
+#[derive(Debug)]
+pub struct SendQueue {
+    burst: f64,
+    refill_ms: u64,
+    tokens: f64,
+    last_refill: u64,
+    high: VecDeque<String>,
+    targets: VecDeque<(String, VecDeque<String>)>,
+}
+
+impl SendQueue {
+    pub fn push(&mut self, priority: Priority, target: &str, line: String) {
+        match priority {
+            Priority::Normal => {},
+            _ => {
+                self.high.push_back(line);
+                return;
+            },
+        }
+        for &mut (ref t, ref mut lines) in self.targets.iter_mut() {
+            if t == target {
+                lines.push_back(line);
+                return;
+            }
+        }
+        let mut lines = VecDeque::new();
+        lines.push_back(line);
+        self.targets.push_back((target.to_string(), lines));
+    }
+
+    pub fn pop(&mut self) -> Option<String> {
+        self.pop_at(time::precise_time_ns())
+    }
+
+    fn pop_at(&mut self, now: u64) -> Option<String> {
+        if self.len() < 1 {
+            return None;
+        }
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return None;
+        }
+        self.tokens -= 1.0;
+        if let Some(line) = self.high.pop_front() {
+            return Some(line);
+        }
+        let (target, mut lines) = match self.targets.pop_front() {
+            Some(t) => t,
+            None => return None,
+        };
+        let line = lines.pop_front();
+        if lines.len() > 0 {
+            self.targets.push_back((target, lines));
+        }
+        line
+    }
+
+    pub fn spend(&mut self) {
+        self.refill(time::precise_time_ns());
+        self.tokens -= 1.0;
+        if self.tokens < 0.0 {
+            self.tokens = 0.0;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.high.len() + self.targets.iter().map(|&(_, ref lines)| lines.len()).sum::<usize>()
+    }
+
+    fn refill(&mut self, now: u64) {
+        let elapsed_ns = now - self.last_refill;
+        self.last_refill = now;
+        if self.refill_ms < 1 {
+            self.tokens = self.burst;
+            return;
+        }
+        self.tokens += elapsed_ns as f64 / (self.refill_ms * 1000000) as f64;
+        if self.tokens > self.burst {
+            self.tokens = self.burst;
+        }
+    }
+}
+
+pub fn new(burst: u32, refill_ms: u64) -> SendQueue {
+    SendQueue {
+        burst: burst as f64,
+        refill_ms: refill_ms,
+        tokens: burst as f64,
+        last_refill: time::precise_time_ns(),
+        high: VecDeque::new(),
+        targets: VecDeque::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new, priority, Priority, SendQueue};
+
+    const MS: u64 = 1000000;
+
+    fn queue(burst: u32, refill_ms: u64) -> SendQueue {
+        let mut queue = new(burst, refill_ms);
+        queue.last_refill = 0;
+        queue
+    }
+
+    fn push_msg(queue: &mut SendQueue, target: &str, text: &str) {
+        queue.push(Priority::Normal, target, format!("PRIVMSG {} :{}", target, text));
+    }
+
+    #[test]
+    fn command_priorities() {
+        assert_eq!(priority("PONG"), Priority::Immediate);
+        assert_eq!(priority("QUIT"), Priority::Immediate);
+        assert_eq!(priority("JOIN"), Priority::High);
+        assert_eq!(priority("MODE"), Priority::High);
+        assert_eq!(priority("PRIVMSG"), Priority::Normal);
+        assert_eq!(priority("NOTICE"), Priority::Normal);
+    }
+
+    #[test]
+    fn burst_then_refill() {
+        let mut queue = queue(3, 1000);
+        for i in 0..5 {
+            push_msg(&mut queue, "#chan", &i.to_string());
+        }
+        assert_eq!(queue.pop_at(0), Some("PRIVMSG #chan :0".to_string()));
+        assert_eq!(queue.pop_at(0), Some("PRIVMSG #chan :1".to_string()));
+        assert_eq!(queue.pop_at(0), Some("PRIVMSG #chan :2".to_string()));
+        assert_eq!(queue.pop_at(0), None);
+        assert_eq!(queue.pop_at(999 * MS), None);
+        assert_eq!(queue.pop_at(1000 * MS), Some("PRIVMSG #chan :3".to_string()));
+        assert_eq!(queue.pop_at(1500 * MS), None);
+        assert_eq!(queue.pop_at(2000 * MS), Some("PRIVMSG #chan :4".to_string()));
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn tokens_are_capped_at_the_burst() {
+        let mut queue = queue(2, 1000);
+        push_msg(&mut queue, "#chan", "a");
+        assert!(queue.pop_at(0).is_some());
+        for i in 0..4 {
+            push_msg(&mut queue, "#chan", &i.to_string());
+        }
+        assert!(queue.pop_at(60000 * MS).is_some());
+        assert!(queue.pop_at(60000 * MS).is_some());
+        assert_eq!(queue.pop_at(60000 * MS), None);
+    }
+
+    #[test]
+    fn high_priority_jumps_the_queue() {
+        let mut queue = queue(10, 1000);
+        push_msg(&mut queue, "#chan", "hello");
+        queue.push(Priority::High, "#chan", "JOIN #other".to_string());
+        assert_eq!(queue.pop_at(0), Some("JOIN #other".to_string()));
+        assert_eq!(queue.pop_at(0), Some("PRIVMSG #chan :hello".to_string()));
+    }
+
+    #[test]
+    fn targets_take_turns() {
+        let mut queue = queue(10, 1000);
+        push_msg(&mut queue, "#a", "1");
+        push_msg(&mut queue, "#a", "2");
+        push_msg(&mut queue, "#a", "3");
+        push_msg(&mut queue, "#b", "1");
+        push_msg(&mut queue, "#c", "1");
+        push_msg(&mut queue, "#b", "2");
+        let order: Vec<String> = (0..6).filter_map(|_| queue.pop_at(0)).collect();
+        assert_eq!(order, vec!["PRIVMSG #a :1", "PRIVMSG #b :1", "PRIVMSG #c :1",
+                               "PRIVMSG #a :2", "PRIVMSG #b :2", "PRIVMSG #a :3"]);
+    }
+
+    #[test]
+    fn no_refill_interval_means_no_limit() {
+        let mut queue = queue(1, 0);
+        for i in 0..5 {
+            push_msg(&mut queue, "#chan", &i.to_string());
+        }
+        assert_eq!((0..5).filter_map(|_| queue.pop_at(0)).count(), 5);
+    }
+}
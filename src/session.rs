@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use casemap::{CaseMapping, IRCKey};
+use irc::Client;
+use permissions::Role;
+
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct Session {
+    pub nick: String,
+    pub username: String,
+    pub address: String,
+    pub account: Option<String>,
+    pub role: Role,
+    pub started: i64,
+    pub last_seen: i64,
+}
+
+impl Session {
+    fn belongs_to(&self, client: &Client) -> bool {
+        self.username == client.username && self.address.eq_ignore_ascii_case(&client.address)
+    }
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+pub struct Sessions {
+    idle_timeout: i64,
+    sessions: HashMap<IRCKey, Session>,
+}
+
+impl Sessions {
+    pub fn start(&mut self, client: &Client, account: Option<String>, role: Role, now: i64,
+                 casemapping: CaseMapping) {
+        self.sessions.insert(casemapping.key(&client.nick), Session {
+            nick: client.nick.to_string(),
+            username: client.username.to_string(),
+            address: client.address.to_string(),
+            account: account,
+            role: role,
+            started: now,
+            last_seen: now,
+        });
+    }
+
+    pub fn get(&self, client: &Client, now: i64, casemapping: CaseMapping) -> Option<&Session> {
+        match self.sessions.get(&casemapping.key(&client.nick)) {
+            Some(s) if s.belongs_to(client) && !self.idle(s, now) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn touch(&mut self, client: &Client, now: i64, casemapping: CaseMapping) {
+        let key = casemapping.key(&client.nick);
+        let active = match self.sessions.get(&key) {
+            Some(s) => s.belongs_to(client) && !self.idle(s, now),
+            None => false,
+        };
+        if active {
+            if let Some(session) = self.sessions.get_mut(&key) {
+                session.last_seen = now;
+            }
+        }
+    }
+
+    pub fn end(&mut self, nick: &str, casemapping: CaseMapping) -> bool {
+        self.sessions.remove(&casemapping.key(nick)).is_some()
+    }
+
+    pub fn rename(&mut self, old_nick: &str, new_nick: &str, casemapping: CaseMapping) {
+        if let Some(mut session) = self.sessions.remove(&casemapping.key(old_nick)) {
+            session.nick = new_nick.to_string();
+            self.sessions.insert(casemapping.key(new_nick), session);
+        }
+    }
+
+    pub fn account_changed(&mut self, nick: &str, account: Option<&str>,
+                           casemapping: CaseMapping) {
+        let changed = match self.sessions.get(&casemapping.key(nick)) {
+            Some(&Session { account: Some(ref a), .. }) => account != Some(a.as_str()),
+            _ => false,
+        };
+        if changed {
+            self.end(nick, casemapping);
+        }
+    }
+
+    pub fn retain<F>(&mut self, keep: F) where F: Fn(&Session) -> bool {
+        self.sessions.retain(|_, s| keep(s));
+    }
+
+    pub fn expire(&mut self, now: i64) {
+        let idle_timeout = self.idle_timeout;
+        self.sessions.retain(|_, s| idle_timeout < 1 || now - s.last_seen < idle_timeout);
+    }
+
+    pub fn clear(&mut self) {
+        self.sessions.clear();
+    }
+
+    fn idle(&self, session: &Session, now: i64) -> bool {
+        self.idle_timeout > 0 && now - session.last_seen >= self.idle_timeout
+    }
+}
+
+pub fn new(idle_timeout: i64) -> Sessions {
+    Sessions {
+        idle_timeout: idle_timeout,
+        sessions: HashMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use casemap::CaseMapping;
+    use irc::Client;
+    use permissions::Role;
+    use super::{new, Sessions};
+
+    const RFC1459: CaseMapping = CaseMapping::Rfc1459;
+
+    fn client(nick: &str, address: &str) -> Client<'static> {
+        Client {
+            nick: nick.to_string().into(),
+            username: "user".into(),
+            address: address.to_string().into(),
+        }
+    }
+
+    fn started(idle_timeout: i64) -> Sessions {
+        let mut sessions = new(idle_timeout);
+        sessions.start(&client("Admin[1]", "host"), Some("admin".to_string()), Role::Owner, 100,
+                       RFC1459);
+        sessions
+    }
+
+    #[test]
+    fn sessions_are_keyed_by_casemapped_nick_and_host() {
+        let sessions = started(0);
+        assert_eq!(sessions.get(&client("admin{1}", "HOST"), 100, RFC1459).map(|s| s.role),
+                   Some(Role::Owner));
+        assert!(sessions.get(&client("Admin[1]", "elsewhere"), 100, RFC1459).is_none());
+        assert!(sessions.get(&client("other", "host"), 100, RFC1459).is_none());
+    }
+
+    #[test]
+    fn idle_sessions_expire_unless_touched() {
+        let mut sessions = started(60);
+        let admin = client("Admin[1]", "host");
+        sessions.touch(&admin, 150, RFC1459);
+        assert!(sessions.get(&admin, 209, RFC1459).is_some());
+        assert!(sessions.get(&admin, 210, RFC1459).is_none());
+        sessions.touch(&admin, 210, RFC1459);
+        assert!(sessions.get(&admin, 210, RFC1459).is_none());
+        sessions.expire(210);
+        assert!(!sessions.end("Admin[1]", RFC1459));
+    }
+
+    #[test]
+    fn no_idle_timeout_keeps_sessions() {
+        let mut sessions = started(0);
+        sessions.expire(1000000);
+        assert!(sessions.get(&client("Admin[1]", "host"), 1000000, RFC1459).is_some());
+    }
+
+    #[test]
+    fn sessions_follow_nick_changes() {
+        let mut sessions = started(0);
+        sessions.rename("ADMIN{1}", "admin_", RFC1459);
+        assert!(sessions.get(&client("Admin[1]", "host"), 100, RFC1459).is_none());
+        let session = sessions.get(&client("Admin_", "host"), 100, RFC1459).unwrap();
+        assert_eq!(session.nick, "admin_");
+        assert_eq!(session.started, 100);
+    }
+
+    #[test]
+    fn account_changes_end_sessions() {
+        let mut sessions = started(0);
+        sessions.account_changed("Admin[1]", Some("admin"), RFC1459);
+        assert!(sessions.get(&client("Admin[1]", "host"), 100, RFC1459).is_some());
+        sessions.account_changed("Admin[1]", None, RFC1459);
+        assert!(sessions.get(&client("Admin[1]", "host"), 100, RFC1459).is_none());
+    }
+
+    #[test]
+    fn ending_and_clearing() {
+        let mut sessions = started(0);
+        assert!(sessions.end("admin{1}", RFC1459));
+        assert!(!sessions.end("admin{1}", RFC1459));
+        let mut sessions = started(0);
+        sessions.retain(|s| s.role < Role::Owner);
+        assert!(!sessions.end("Admin[1]", RFC1459));
+        let mut sessions = started(0);
+        sessions.clear();
+        assert!(!sessions.end("Admin[1]", RFC1459));
+    }
+}
@@ -0,0 +1,140 @@
+pub const MORE_SUFFIX: &'static str = " \u{2026}(more)";
+
+pub fn split_message(text: &str, budget: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    let mut style = ::formatting::Style::default();
+    let mut codes = String::new();
+    while codes.len() + rest.len() > budget {
+        if codes.len() * 2 > budget {
+            codes.clear();
+            continue;
+        }
+        let room = budget - codes.len();
+        let cut = match find_cut(rest, room) {
+            Some(c) => c,
+            None => hard_cut(rest),
+        };
+        lines.push(format!("{codes}{line}", codes=codes, line=rest[0..cut].trim_end_matches(' ')));
+        ::formatting::apply_codes(&mut style, &rest[0..cut]);
+        rest = rest[cut..].trim_start_matches(' ');
+        codes = ::formatting::opening_codes(&style, rest);
+    }
+    if rest.len() > 0 || lines.len() < 1 {
+        lines.push(format!("{codes}{rest}", codes=codes, rest=rest));
+    }
+    lines
+}
+
+fn find_cut(text: &str, budget: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut last_safe = None;
+    let mut last_space = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        let next = i + ::formatting::code_len(&bytes[i..]).unwrap_or_else(|| char_len(bytes[i]));
+        if next > budget {
+            break;
+        }
+        if bytes[i] == b' ' && i > 0 {
+            last_space = Some(i);
+        }
+        i = next;
+        last_safe = Some(i);
+    }
+    match last_space {
+        Some(s) if s > budget / 2 => Some(s),
+        _ => last_safe,
+    }
+}
+
+/// Used when not even the first character or formatting code fits: that
+/// unit goes on a line of its own, overlong, rather than being torn apart.
+fn hard_cut(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    ::formatting::code_len(bytes).unwrap_or_else(|| char_len(bytes[0]))
+}
+
+fn char_len(byte: u8) -> usize {
+    match byte {
+        0xf0..=0xff => 4,
+        0xe0..=0xef => 3,
+        0xc0..=0xdf => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use formatting::{self, Style};
+    use test_util::Rng;
+    use super::split_message;
+
+    const PIECES: [&'static str; 12] = [
+        "a", "word", ",", "\u{e9}", "\u{2026}", "\u{1f980}", "\x02", "\x1d", "\x0f",
+        "\x0304", "\x0312,01", "\x03",
+    ];
+
+    fn styled_chars(lines: &[String]) -> Vec<(char, Style)> {
+        let mut chars = Vec::new();
+        for line in lines.iter() {
+            for span in formatting::parse(line) {
+                let visible = span.text.chars().filter(|c| *c != ' ');
+                chars.extend(visible.map(|c| (c, span.style.clone())));
+            }
+        }
+        chars
+    }
+
+    #[test]
+    fn short_messages_are_untouched() {
+        assert_eq!(split_message("hello world", 20), vec!["hello world"]);
+        assert_eq!(split_message("", 20), vec![""]);
+    }
+
+    #[test]
+    fn splits_on_word_boundaries() {
+        assert_eq!(split_message("aaa bbb ccc ddd", 8), vec!["aaa bbb", "ccc ddd"]);
+        assert_eq!(split_message("aaaaaaaaaaaa", 5), vec!["aaaaa", "aaaaa", "aa"]);
+    }
+
+    #[test]
+    fn never_splits_utf8_sequences() {
+        let lines = split_message("\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}", 5);
+        assert_eq!(lines, vec!["\u{e9}\u{e9}", "\u{e9}\u{e9}", "\u{e9}"]);
+    }
+
+    #[test]
+    fn hard_cut_when_nothing_fits() {
+        let lines = split_message("\x0312,01colour", 4);
+        assert_eq!(lines, vec!["\x0312,01", "colo", "ur"]);
+        let lines = split_message("\x04ff00ff\u{e9}t\u{e9}", 1);
+        assert_eq!(lines, vec!["\x04ff00ff", "\u{e9}", "t", "\u{e9}"]);
+    }
+
+    #[test]
+    fn formatting_continues_on_the_next_line() {
+        let lines = split_message("\x02\x0304bold red words here\x0f plain", 16);
+        assert_eq!(lines, vec!["\x02\x0304bold red", "\x02\x0304words here\x0f", "plain"]);
+        let lines = split_message("\x0304red ,comma", 11);
+        assert_eq!(lines, vec!["\x0304red", "\x0304\x02\x02,comma"]);
+    }
+
+    #[test]
+    fn generated_messages_keep_text_and_style() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for _ in 0..500 {
+            let mut text = String::new();
+            for _ in 0..rng.below(60) {
+                text.push_str(PIECES[rng.below(PIECES.len())]);
+                if rng.below(3) == 0 {
+                    text.push(' ');
+                }
+            }
+            let budget = 30 + rng.below(40);
+            let lines = split_message(&text, budget);
+            assert!(lines.iter().all(|l| l.len() <= budget), "{:?} {:?}", text, lines);
+            assert_eq!(styled_chars(&lines), styled_chars(&[text.clone()]), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::thread;
+use std::time::Duration;
+use time;
+
+const MIN_BACKOFF_SECONDS: u64 = 2;
+const MAX_BACKOFF_SECONDS: u64 = 300;
+
+pub fn run(config: &::config::Config, server: ::config::Server) {
+    let addresses = server.addresses();
+    let mut bot = ::bot::new(config, server.clone());
+    let mut failures: u32 = 0;
+    let mut next_address = 0;
+    loop {
+        let address = &addresses[next_address % addresses.len()];
+        next_address += 1;
+        match ::connection::connect(server.clone(), address) {
+            Ok(connection) => {
+                let mut irc = ::irc::new(connection, config, bot);
+                let result = irc.run();
+                if irc.registered() {
+                    failures = 0;
+                    next_address = 0;
+                }
+                match result {
+                    Ok(_) => println!("Connection to {address} closed.", address=address),
+                    Err(err) => println!("Lost connection to {address}: {err}",
+                                         address=address,
+                                         err=err),
+                }
+                bot = irc.into_bot();
+            },
+            Err(err) => println!("Could not connect to {address}: {err}", address=address, err=err),
+        }
+        failures += 1;
+        let delay = backoff(failures);
+        println!("Reconnecting to {server} in {delay} ms", server=server, delay=delay);
+        thread::sleep(Duration::from_millis(delay));
+    }
+}
+
+fn backoff(failures: u32) -> u64 {
+    backoff_with_jitter(failures, time::precise_time_ns())
+}
+
+/// The delay in milliseconds before the next attempt. It doubles from
+/// MIN_BACKOFF_SECONDS up to MAX_BACKOFF_SECONDS and is spread by up to a
+/// quarter either way, picked by `entropy`.
+fn backoff_with_jitter(failures: u32, entropy: u64) -> u64 {
+    let exponent = match failures {
+        0 => 0,
+        f if f > 16 => 16,
+        f => f - 1,
+    };
+    let mut seconds = MIN_BACKOFF_SECONDS << exponent;
+    if seconds > MAX_BACKOFF_SECONDS {
+        seconds = MAX_BACKOFF_SECONDS;
+    }
+    let jitter_range = seconds * 1000 / 2;
+    let jitter = entropy % (jitter_range + 1);
+    seconds * 1000 - jitter_range / 2 + jitter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff, backoff_with_jitter, MAX_BACKOFF_SECONDS, MIN_BACKOFF_SECONDS};
+
+    fn bounds(seconds: u64) -> (u64, u64) {
+        (seconds * 750, seconds * 1250)
+    }
+
+    #[test]
+    fn first_failures_start_at_the_minimum() {
+        let (low, high) = bounds(MIN_BACKOFF_SECONDS);
+        for failures in 0..2 {
+            assert_eq!(backoff_with_jitter(failures, 0), low);
+            assert_eq!(backoff_with_jitter(failures, high - low), high);
+            let delay = backoff(failures);
+            assert!(delay >= low && delay <= high, "{}", delay);
+        }
+    }
+
+    #[test]
+    fn delay_doubles_until_the_cap() {
+        let mut previous = 0;
+        for failures in 1..9 {
+            let delay = backoff_with_jitter(failures, 0);
+            assert!(delay > previous);
+            previous = delay;
+        }
+        let capped = bounds(MAX_BACKOFF_SECONDS).0;
+        assert_eq!(backoff_with_jitter(2, 0), bounds(4).0);
+        assert_eq!(backoff_with_jitter(8, 0), bounds(256).0);
+        assert_eq!(backoff_with_jitter(9, 0), capped);
+        for failures in &[17, 31, 32, 1000, u32::max_value()] {
+            assert_eq!(backoff_with_jitter(*failures, 0), capped);
+        }
+    }
+
+    #[test]
+    fn jitter_stays_within_a_quarter() {
+        for failures in &[1, 3, 31, u32::max_value()] {
+            let centre = backoff_with_jitter(*failures, 0) / 3 * 4;
+            let (low, high) = bounds(centre / 1000);
+            let mut seen = (u64::max_value(), 0);
+            for entropy in 0..high {
+                let delay = backoff_with_jitter(*failures, entropy * 7919);
+                assert!(delay >= low && delay <= high, "{} {}", failures, delay);
+                seen = (seen.0.min(delay), seen.1.max(delay));
+            }
+            assert_eq!(seen, (low, high));
+        }
+    }
+}
@@ -0,0 +1,21 @@
+/// Deterministic xorshift generator for the generated-input tests, so a
+/// failure always reproduces with the same seed.
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub fn string(&mut self, chars: &[u8], min: usize, max: usize) -> String {
+        let len = min + self.below(max - min + 1);
+        (0..len).map(|_| chars[self.below(chars.len())] as char).collect()
+    }
+}